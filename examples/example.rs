@@ -1,4 +1,4 @@
-use std::{thread, time::Duration};
+use std::{thread, time::Duration, sync::{Arc, atomic::{AtomicU64, Ordering}}};
 
 fn main() {
 	// Default
@@ -16,6 +16,13 @@ fn main() {
 		thread::sleep(Duration::from_millis(1));
 	}
 
+	// All presets
+	for (name, preset) in progression::Config::presets() {
+		for _ in progression::bar_with_config(0..300, progression::Config { prefix: name, ..preset() }) {
+			thread::sleep(Duration::from_millis(1));
+		}
+	}
+
 	// Uses `slice.chunks` internally for lower overhead on large numbers of items
 	for _ in progression::bar_chunks(10, &[0; 1_000]) {
 		thread::sleep(Duration::from_millis(1));
@@ -26,6 +33,74 @@ fn main() {
 		thread::sleep(Duration::from_millis(1));
 	}
 
+	// Times in a format of your own, right-aligned to 6 columns
+	let format_time: progression::FormatTime = Arc::new(|time: Duration, _| format!("{}s", time.as_secs()));
+
+	for _ in progression::bar_with_config(0..1_000, progression::Config { format_time: Some((format_time, 6)), ..Default::default() }) {
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	// Live readout of domain-specific numbers after the ETA
+	let errors = Arc::new(AtomicU64::new(0));
+	let extra = progression::Config {
+		extra: Some(Arc::new({
+			let errors = errors.clone();
+			move |_: &progression::ProgressSnapshot| format!("{} errors", errors.load(Ordering::Relaxed))
+		})),
+		..progression::Config::cargo()
+	};
+
+	for i in progression::bar_with_config(0..1_000, extra) {
+		if i % 97 == 0 {
+			errors.fetch_add(1, Ordering::Relaxed);
+		}
+
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	// Sequential phases, each bar prefixed with `[n/3] name`
+	let mut phases = progression::Phases::new(&["fetch", "build", "test"], progression::Config::cargo());
+
+	for phase in 0..phases.len() {
+		let bar = phases.start(phase, 300);
+
+		for _ in 0..300 {
+			thread::sleep(Duration::from_millis(1));
+			bar.inc(1);
+		}
+	}
+
+	// Multiple bars and their total, rows of finished bars are reused by the bars added after them
+	let multi = progression::MultiBar::with_max_rows(4);
+	multi.show_total(progression::TotalRow::Bottom, progression::Config { prefix: "total ", ..progression::Config::cargo() });
+
+	thread::scope(|scope| {
+		for _ in 0..8 {
+			scope.spawn(|| {
+				let bar = multi.add(300, progression::Config::cargo());
+
+				for _ in 0..300 {
+					thread::sleep(Duration::from_millis(1));
+					bar.inc(1);
+				}
+			});
+		}
+	});
+
+	// A download that only learns its length after a while, spinning until then
+	let bar = progression::Bar::unbounded(progression::Config { bytes: Some(progression::ByteUnits::Binary), ..progression::Config::cargo() });
+
+	for chunk in 0..300 {
+		if chunk == 100 {
+			bar.set_len(300 * 4096);
+		}
+
+		thread::sleep(Duration::from_millis(5));
+		bar.inc(4096);
+	}
+
+	bar.finish();
+
 	// Manual
 	let items = vec![1, 2, 3, 4, 5];
 	let bar = progression::Bar::new(items.len() as u64, progression::Config { prefix: "(items) ", ..progression::Config::cargo() });
@@ -0,0 +1,71 @@
+use std::{io::Write, sync::{Arc, Mutex}};
+
+use crate::{LogWriter, Sink, strip_escapes};
+
+/// Collects rendered frames for assertions, either through `writer` as `Config::writer` (or the writer of a
+/// `MultiBar`), receiving the raw terminal output, or through `sink` as `Config::sink`. The output is split into frames
+/// on `\r` and `\n`, and escape sequences are stripped. Clones share the frames.
+#[derive(Clone, Default)]
+pub struct FrameCapture {
+	inner: Arc<Mutex<Capture>>,
+}
+
+#[derive(Default)]
+struct Capture {
+	frames: Vec<String>,
+	// Bytes after the last frame separator
+	partial: Vec<u8>,
+}
+
+impl FrameCapture {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// A `Config::sink` adding every frame to this capture.
+	pub fn sink(&self) -> Sink {
+		let capture = self.clone();
+		Arc::new(move |line| capture.inner.lock().unwrap().frames.push(strip_escapes(line).into_owned()))
+	}
+
+	/// A `Config::writer` adding the frames it receives to this capture.
+	pub fn writer(&self) -> LogWriter {
+		Arc::new(Mutex::new(self.clone()))
+	}
+
+	pub fn frames(&self) -> Vec<String> {
+		self.inner.lock().unwrap().frames.clone()
+	}
+
+	pub fn last_frame(&self) -> Option<String> {
+		self.inner.lock().unwrap().frames.last().cloned()
+	}
+}
+
+impl Write for FrameCapture {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let mut capture = self.inner.lock().unwrap();
+
+		for &byte in buf {
+			if byte == b'\r' || byte == b'\n' {
+				let partial = std::mem::take(&mut capture.partial);
+				let frame = strip_escapes(&String::from_utf8_lossy(&partial)).into_owned();
+
+				// Cursor movements alone between separators aren't frames
+				if !frame.is_empty() {
+					capture.frames.push(frame);
+				}
+			} else {
+				capture.partial.push(byte);
+			}
+		}
+
+		Ok(buf.len())
+	}
+
+	#[inline]
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
@@ -0,0 +1,16 @@
+use std::time::Instant;
+
+/// The source of time for throttling, the ETA and the elapsed time. Replacing it lets tests advance time by hand
+/// instead of sleeping. The steady tick thread still waits in real time, but reads the time from the clock.
+pub trait Clock: Send + Sync {
+	fn now(&self) -> Instant;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+	#[inline]
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+/// How the ETA is extrapolated. `Recent` and `Linear` work on a window of `Config::eta_samples` samples of the
+/// position, taken at most every half a second as the bar is redrawn (plus the latest one), so the default of 16
+/// samples covers the last 8 seconds or more.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum EtaMode {
+	/// The average rate since the start.
+	#[default]
+	Average,
+	/// The rate between the oldest and the newest sample, which follows changes in speed.
+	Recent,
+	/// A least-squares line fitted to the samples, which is less sensitive to bursts than `Recent`. Costs a pass over
+	/// the samples per redraw.
+	Linear,
+}
+
+const SAMPLE_INTERVAL_SECS: f64 = 0.5;
+
+pub(crate) struct Samples {
+	// (seconds since the start, position)
+	samples: VecDeque<(f64, f64)>,
+	capacity: usize,
+}
+
+impl Samples {
+	pub(crate) fn new(capacity: usize) -> Self {
+		let capacity = capacity.max(2);
+		Self { samples: VecDeque::with_capacity(capacity), capacity }
+	}
+
+	pub(crate) fn push(&mut self, secs: f64, pos: u64) {
+		let len = self.samples.len();
+
+		// The newest sample is kept up to date until it is far enough from the one before it
+		if len >= 2 && secs - self.samples[len - 2].0 < SAMPLE_INTERVAL_SECS {
+			self.samples[len - 1] = (secs, pos as f64);
+			return;
+		}
+
+		if len == self.capacity {
+			self.samples.pop_front();
+		}
+
+		self.samples.push_back((secs, pos as f64));
+	}
+
+	/// Steps per second between the oldest and the newest sample.
+	pub(crate) fn recent_rate(&self) -> Option<f64> {
+		let (&(first_secs, first_pos), &(last_secs, last_pos)) = (self.samples.front()?, self.samples.back()?);
+		(last_secs > first_secs).then(|| (last_pos - first_pos) / (last_secs - first_secs))
+	}
+
+	/// Slope of the line `pos = intercept + slope * secs` closest to the samples, in steps per second.
+	pub(crate) fn linear_rate(&self) -> Option<f64> {
+		let n = self.samples.len() as f64;
+		let mean_secs = self.samples.iter().map(|&(secs, _)| secs).sum::<f64>() / n;
+		let mean_pos = self.samples.iter().map(|&(_, pos)| pos).sum::<f64>() / n;
+		let (covariance, variance) = self.samples.iter().fold((0., 0.), |(covariance, variance), &(secs, pos)| {
+			(covariance + (secs - mean_secs) * (pos - mean_pos), variance + (secs - mean_secs).powi(2))
+		});
+
+		(variance > 0.).then(|| covariance / variance)
+	}
+
+	/// Mean and standard deviation of the rates between neighbouring samples, in steps per second.
+	pub(crate) fn rate_spread(&self) -> Option<(f64, f64)> {
+		let rates = || self.samples.iter().zip(self.samples.iter().skip(1))
+			.filter(|((first_secs, _), (secs, _))| secs > first_secs)
+			.map(|((first_secs, first_pos), (secs, pos))| (pos - first_pos) / (secs - first_secs));
+		let n = rates().count() as f64;
+
+		if n < 2. {
+			return None;
+		}
+
+		let mean = rates().sum::<f64>() / n;
+		let variance = rates().map(|rate| (rate - mean).powi(2)).sum::<f64>() / n;
+		Some((mean, variance.sqrt()))
+	}
+}
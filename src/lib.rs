@@ -1,9 +1,32 @@
-use std::{io::{stderr, Write}, fmt::Display, time::Instant, sync::atomic::{AtomicU64, Ordering::SeqCst}};
+#[cfg(any(test, feature = "test-util"))]
+mod capture;
+mod clock;
+mod eta;
+mod width;
+mod multi;
+#[cfg(any(test, all(windows, feature = "windows-console")))]
+mod windows;
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "test-util")]
+pub use capture::FrameCapture;
+#[doc(hidden)]
+pub use clock::Clock;
+pub use eta::EtaMode;
+pub use multi::{FinishedRow, MultiBar, TotalRow};
+use clock::SystemClock;
+use eta::Samples;
+use width::{char_width, text_width};
+use multi::Row;
+
+use std::{io::Write, borrow::Cow, fs::File, fmt::{Debug, Display, Write as _}, marker::PhantomData, ops::Deref, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use std::{panic::{self, AssertUnwindSafe}, sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, atomic::{AtomicBool, AtomicU64, Ordering::SeqCst}}};
 
 #[cfg(feature = "num-format")]
-use num_format::{Locale, ToFormattedString, ToFormattedStr};
+use num_format::{Locale, ToFormattedString};
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Style {
 	Mono(char),
 	Edged(char, char),
@@ -19,6 +42,232 @@ impl Style {
 	}
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteUnits {
+	/// KiB, MiB, … (powers of 1024)
+	Binary,
+	/// kB, MB, … (powers of 1000)
+	Decimal,
+}
+
+impl ByteUnits {
+	fn format(self, bytes: u64) -> String {
+		let (base, suffixes) = match self {
+			Self::Binary => (1024., ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+			Self::Decimal => (1000., ["kB", "MB", "GB", "TB", "PB", "EB"]),
+		};
+
+		if (bytes as f64) < base {
+			return format!("{bytes} B");
+		}
+
+		let mut value = bytes as f64 / base;
+		let mut suffix = suffixes[0];
+
+		for next in &suffixes[1..] {
+			if value < base {
+				break;
+			}
+
+			value /= base;
+			suffix = next;
+		}
+
+		format!("{value:.1} {suffix}")
+	}
+}
+
+/// The standard terminal colors, rendered with SGR escapes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Color {
+	Black,
+	Red,
+	Green,
+	Yellow,
+	Blue,
+	Magenta,
+	Cyan,
+	White,
+}
+
+impl Color {
+	fn sgr(self) -> &'static str {
+		match self {
+			Self::Black => "\x1b[30m",
+			Self::Red => "\x1b[31m",
+			Self::Green => "\x1b[32m",
+			Self::Yellow => "\x1b[33m",
+			Self::Blue => "\x1b[34m",
+			Self::Magenta => "\x1b[35m",
+			Self::Cyan => "\x1b[36m",
+			Self::White => "\x1b[37m",
+		}
+	}
+}
+
+/// What makes `inc` redraw the bar. `throttle_steps` and `min_redraw_interval` apply in every mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ThrottleMode {
+	/// Once `throttle` has passed since the last redraw.
+	#[default]
+	Time,
+	/// Once the whole percentage changes, which keeps the number of redraws of long runs down to about a hundred.
+	Percent,
+	/// Whichever of the two comes first.
+	TimeOrPercent,
+}
+
+/// How the elapsed time and the ETA are shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TimeFormat {
+	/// `00:02:13`
+	#[default]
+	Clock,
+	/// `45s`, `2m 13s`, `1h 02m`, `3d 4h`: at most two units, without the seconds past an hour.
+	Human,
+}
+
+/// Which time `Config::format_time` is formatting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeField {
+	/// Also shown after a failure
+	Elapsed,
+	Eta,
+}
+
+impl TimeFormat {
+	// Every time takes the same width, so that the bar doesn't jump as it changes
+	fn width(self) -> usize {
+		match self {
+			Self::Clock => "00:00:00".len(),
+			Self::Human => "99d 23h".len(),
+		}
+	}
+
+	fn unknown(self) -> &'static str {
+		match self {
+			Self::Clock => "--:--:--",
+			Self::Human => "     --",
+		}
+	}
+}
+
+/// The words on the line, e.g. for translating it. Each is followed by a space, unless it is empty.
+///
+/// ```
+/// # use std::{borrow::Cow, sync::{Arc, Mutex}};
+/// # use progression::{Bar, Config, Labels, MultiBar};
+/// let labels = Labels {
+///     elapsed: Cow::Borrowed("verstrichen"), eta: Cow::Borrowed("noch"), failed: Cow::Borrowed("fehlgeschlagen nach"),
+///     separator: Cow::Borrowed(" von "), average: Cow::Borrowed("Ø"), current: Cow::Borrowed("akt."),
+///     done: Cow::Borrowed("fertig"), remaining: Cow::Borrowed("übrig"), more: Cow::Borrowed("weitere"),
+/// };
+/// let line = Arc::new(Mutex::new(String::new()));
+/// let sink = line.clone();
+/// let config = Config {
+///     width: Some(120), show_elapsed: true, show_rate: true, show_current_rate: true, labels: labels.clone(),
+///     sink: Some(Arc::new(move |text| *sink.lock().unwrap() = text.to_owned())), ..Config::default()
+/// };
+/// Bar::new(10, config).inc(5);
+/// let line = line.lock().unwrap().clone();
+/// assert!(["verstrichen", " fertig ", " von ", "noch"].iter().all(|label| line.contains(label)));
+/// assert!(!line.contains("ETA") && !line.contains(" / "));
+///
+/// let block = Arc::new(Mutex::new(Vec::new()));
+/// let multi = MultiBar::with_writer(1, block.clone());
+/// multi.set_labels(labels);
+/// let _bars = [multi.add(10, Config::default()), multi.add(10, Config::default())];
+/// assert!(String::from_utf8_lossy(&block.lock().unwrap()).contains("+1 weitere"));
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Labels {
+	/// Before the elapsed time, none by default
+	pub elapsed: Cow<'static, str>,
+	pub eta: Cow<'static, str>,
+	/// In place of `eta` once the bar has failed, before the elapsed time
+	pub failed: Cow<'static, str>,
+	/// Between the position and the length, spaces included
+	pub separator: Cow<'static, str>,
+	/// Before the average and the current rate when both are shown
+	pub average: Cow<'static, str>,
+	pub current: Cow<'static, str>,
+	/// Before the count, none by default
+	pub done: Cow<'static, str>,
+	/// Before the count with `Config::countdown`, none by default
+	pub remaining: Cow<'static, str>,
+	/// After the number of bars that don't fit into a `MultiBar`, e.g. `+12 more` (see `MultiBar::set_labels`)
+	pub more: Cow<'static, str>,
+}
+
+impl Labels {
+	fn count(&self, countdown: bool) -> &str {
+		if countdown { &self.remaining } else { &self.done }
+	}
+
+	// Like the other text of the line, e.g. a newline in `more` would throw off the lines of a `MultiBar`
+	fn check(&self) -> Result<(), ConfigError> {
+		let Self { elapsed, eta, failed, separator, average, current, done, remaining, more } = self;
+		let labels = [
+			("labels.elapsed", elapsed), ("labels.eta", eta), ("labels.failed", failed), ("labels.separator", separator),
+			("labels.average", average), ("labels.current", current), ("labels.done", done), ("labels.remaining", remaining),
+			("labels.more", more),
+		];
+
+		match labels.into_iter().find(|(_, label)| strip_escapes(label).contains(char::is_control)) {
+			Some((field, _)) => Err(ConfigError::ControlCharacter { field }),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Default for Labels {
+	fn default() -> Self {
+		Self {
+			elapsed: Cow::Borrowed(""),
+			eta: Cow::Borrowed("ETA"),
+			failed: Cow::Borrowed("failed after"),
+			separator: Cow::Borrowed(" / "),
+			average: Cow::Borrowed("avg"),
+			current: Cow::Borrowed("cur"),
+			done: Cow::Borrowed(""),
+			remaining: Cow::Borrowed(""),
+			more: Cow::Borrowed("more"),
+		}
+	}
+}
+
+/// Rounding of the filled part of the bar to whole cells.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Rounding {
+	#[default]
+	Round,
+	/// Never shows more progress than the percentage.
+	Floor,
+	/// Fills a cell as soon as any progress falls into it.
+	Ceil,
+}
+
+/// What a bar dropped by a panic, e.g. in the body of a loop over `bar`, draws instead of a final frame that would
+/// make the work look done. The panic message itself comes before that, as the panic hook runs before the unwinding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PanicBehavior {
+	/// Moves the cursor to the next line, so that whatever comes next starts on a line of its own.
+	#[default]
+	Newline,
+	/// Draws the final frame like when the bar isn't dropped by a panic. A loop over `bar` shows it as failed.
+	FullFrame,
+	/// Leaves the cursor at the start of the line of the last frame.
+	Nothing,
+}
+
+pub type Sink = Arc<dyn Fn(&str) + Send + Sync>;
+pub type UpdateCallback = Arc<dyn Fn(&ProgressSnapshot) + Send + Sync>;
+pub type LogWriter = Arc<Mutex<dyn Write + Send>>;
+pub type ExtraSegment = Arc<dyn Fn(&ProgressSnapshot) -> String + Send + Sync>;
+pub type ProgressCallback = Arc<dyn Fn(u64, u64) + Send + Sync>;
+pub type FormatTime = Arc<dyn Fn(Duration, TimeField) -> String + Send + Sync>;
+pub type Preset = (&'static str, fn() -> Config<'static>);
+
 #[derive(Clone)]
 pub struct Config<'a> {
 	pub width: Option<u64>,
@@ -29,26 +278,364 @@ pub struct Config<'a> {
 	pub prefix: &'a str,
 	pub unit: &'a str,
 	pub num_width: usize,
-	pub throttle_millis: u64,
+	/// Time between redraws from `inc`. Zero redraws on every call whatever `throttle_mode` and `min_redraw_interval`
+	/// say, e.g. for tests and demos. Every redraw formats the line and writes it to the terminal, so a loop that calls
+	/// `inc` millions of times becomes as slow as the terminal.
+	pub throttle: Duration,
+	pub bytes: Option<ByteUnits>,
+	/// Draw from a background thread every `throttle` instead of from `inc`, so that `inc` never blocks on a
+	/// slow terminal.
+	pub render_thread: bool,
+	/// Also redraw once this many steps have accumulated since the last redraw, even within `throttle`.
+	pub throttle_steps: Option<u64>,
+	pub eta: EtaMode,
+	pub eta_samples: usize,
+	/// Receives every frame (without control characters) instead of the terminal. Without the `terminal` feature, a bar
+	/// with neither this, `writer` nor `terminal` draws nothing.
+	pub sink: Option<Sink>,
+	/// Called with every frame, whether or not it is drawn, and once more with `finished` set when the bar finishes,
+	/// including without a final frame, e.g. with `finish_and_clear`. A callback that panics isn't called again.
+	pub on_update: Option<UpdateCallback>,
+	/// Color of the filled part of the bar. Ignored when the output is not a terminal or `NO_COLOR` is set.
+	pub color: Option<Color>,
+	/// Shows the average rate after the ETA, e.g. `2.4 MiB/s` in byte mode.
+	pub show_rate: bool,
+	/// Caps the redraw rate: redraws from `inc`, `throttle_steps` and the steady tick alike are at least this far apart.
+	pub min_redraw_interval: Duration,
+	/// Redraw at least this often even when `inc` isn't called, from a steady tick started with the bar.
+	/// `min_redraw_interval` still applies to these redraws, so it wins if it is the longer of the two.
+	pub max_redraw_interval: Option<Duration>,
+	/// Label of the rate, e.g. `rows` for `rows/s`. Defaults to `unit`; ignored in byte mode, which scales the rate like
+	/// the counts.
+	pub rate_unit: &'a str,
+	/// Shows what is left rather than what is done: the count, a bar that empties and the percentage all count down to
+	/// zero. The snapshot and the ETA are unaffected.
+	pub countdown: bool,
+	/// Shows the rate over the last `eta_samples` redraws after the ETA, next to the average one if `show_rate` is also
+	/// set.
+	pub show_current_rate: bool,
+	/// How the filled part is rounded to whole cells.
+	pub rounding: Rounding,
+	pub show_elapsed: bool,
+	pub show_count: bool,
+	pub show_bar: bool,
+	pub show_eta: bool,
+	/// The percentage, e.g. ` 42%`, which a layout with the counts may do without, leaving its room to the bar.
+	pub show_percent: bool,
+	/// Never draws less of the bar than in the previous frame (more, for `countdown`), so that it doesn't flicker
+	/// between two widths as the message resizes it.
+	pub hysteresis: bool,
+	/// When stderr isn't a terminal, e.g. in CI logs, frames aren't redrawn, but printed on lines of their own this
+	/// often, plus the first and the final one. The same goes for the lines written to `log`.
+	pub log_interval: Duration,
+	/// Draws `delimiters` around the bar. Without them, the bar takes their two columns.
+	pub show_delimiters: bool,
+	pub throttle_mode: ThrottleMode,
+	/// Also receives a line every `log_interval`, plus the first and the final one, e.g. for a log file. Write errors are
+	/// ignored.
+	pub log: Option<LogWriter>,
+	/// Replaces the edge of the bar in the final frame with the first character (in green, if colors are enabled)
+	/// when the bar is finished, or the second (in red) when it is abandoned. Without a bar, it is shown before the
+	/// percentage.
+	pub finish_icon: Option<(char, char)>,
+	/// Rings the terminal bell once the bar finishes, if stderr is a terminal.
+	pub bell: bool,
+	/// Called with every frame, its result shown at the end of the line, e.g. for a live error count. Shrinks the bar
+	/// like the message and is truncated if it doesn't fit. A closure that panics isn't called again.
+	pub extra: Option<ExtraSegment>,
+	/// The ETA and the rates are shown as `--` until this much time has passed or 1% of `len` has been done, whichever
+	/// comes first, as extrapolating from the first few steps gives wild numbers. Only the steps done since the bar was
+	/// created count, so an observed counter that starts halfway warms up like a fresh one.
+	pub eta_warmup: Duration,
+	/// Overlays the count, e.g. `512/1,024`, on the middle of the bar, for a compact layout along with
+	/// `show_count: false`. Left out when the bar is too short for it.
+	pub count_in_bar: bool,
+	/// Draws to this terminal device, e.g. an opened tty or one pane of a multiplexer, instead of stderr. The width and
+	/// whether it is a terminal at all are queried from it too. Bars in a `MultiBar` draw where the block does regardless.
+	/// Unlike stderr, it is drawn to without the `terminal` feature too.
+	pub terminal: Option<Arc<File>>,
+	/// Spaces between the counts (or whatever comes before the bar) and the bar.
+	pub bar_gap: usize,
+	pub time_format: TimeFormat,
+	/// Tracks the shortest and the longest time per step between `inc` calls for `Bar::stats`, at the cost of a read of
+	/// the clock per call.
+	pub step_stats: bool,
+	/// The fewest cells of the bar (its edge included, the delimiters not) that `Bar::try_new` accepts. `Bar::new`
+	/// draws whatever fits.
+	pub min_bar_width: u64,
+	/// Shows the elapsed time and the ETA under 10 seconds with tenths, e.g. `ETA 1.4s`, for runs too short for whole
+	/// seconds to say much.
+	pub precise_seconds: bool,
+	/// Leaves the track empty until the first cell fills instead of showing a lone edge, e.g. `>` in the cargo style.
+	pub hide_empty_edge: bool,
+	/// Keeps the bar on the bottom row of the terminal, with everything else written to it scrolling above, even output
+	/// that doesn't go through `println`. The bottom row is reserved with a scroll region, which is reset when the bar
+	/// is finished or dropped, including by a panic, but not if it is leaked. Only applies to a terminal of a known
+	/// height, and not to bars in a `MultiBar`. Resizing the terminal while the bar is shown leaves it misplaced.
+	pub pinned: bool,
+	/// Also writes the final frame, without colors, to stderr when drawing to `terminal`, e.g. for a log that stderr is
+	/// redirected to.
+	pub summary_to_stderr: bool,
+	/// Separates the thousands in counts and rates, e.g. `1,234,567`. Ignored with the `num-format` feature, which is on
+	/// by default and groups them for the English locale, so it only takes effect with `default-features = false`.
+	pub group_separator: char,
+	pub labels: Labels,
+	/// Shown at the end of the line, after the rate and before `extra`, e.g. a status word. It takes room from the bar like
+	/// `prefix`.
+	pub suffix: &'a str,
+	/// Pause after every frame drawn by `inc`, which paces a loop to the frames rather than the other way around.
+	pub frame_delay: Duration,
+	/// Shows the ETA as a range, e.g. `ETA 00:02:00–00:05:00`, while the rate between the samples (see `EtaMode`)
+	/// deviates from its mean by more than a fifth, and as a single time otherwise. The range spans one standard
+	/// deviation of the rate either way, and is open-ended when the rate may drop to nothing.
+	pub eta_range: bool,
+	/// Formats the elapsed time and the ETA in place of `time_format`, e.g. for another language or `about a minute`.
+	/// The times are right-aligned to the width that comes with it, and cut short if they are wider.
+	///
+	/// ```
+	/// # use std::{sync::{Arc, Mutex}, time::Duration};
+	/// # use progression::{Bar, Config, TimeField};
+	/// let about = |time: Duration, _: TimeField| match time.as_secs() {
+	///     0..60 => "under a minute".to_owned(),
+	///     secs => format!("about {} min", secs.div_ceil(60)),
+	/// };
+	/// let line = Arc::new(Mutex::new(String::new()));
+	/// let sink = line.clone();
+	/// let config = Config { format_time: Some((Arc::new(about), 14)), width: Some(80),
+	///     sink: Some(Arc::new(move |text| *sink.lock().unwrap() = text.to_owned())), ..Config::default() };
+	/// Bar::new(10, config).inc(10);
+	/// assert!(line.lock().unwrap().starts_with(" under a minute 10 / 10 "));
+	/// ```
+	pub format_time: Option<(FormatTime, usize)>,
+	/// Called with the position and the length from `inc` and the like whenever the whole percentage changes, e.g. for
+	/// a checkpoint every 10%, and not for the steps in between. A callback that panics isn't called again.
+	pub on_progress: Option<ProgressCallback>,
+	pub on_panic: PanicBehavior,
+	/// Draws to this writer instead of stderr or `terminal`, e.g. a `FrameCapture` in tests. The frames are redrawn in
+	/// place as on a terminal, without colors, and the line is `width` (or `default_width`) wide.
+	pub writer: Option<LogWriter>,
 }
 
 impl Config<'_> {
 	#[inline]
 	pub fn ascii() -> Self {
-		Self { style: Style::Mono('#'), ..Default::default() }
+		Self { style: Style::Mono('#'), finish_icon: Some(('v', 'x')), ..Default::default() }
 	}
 
 	#[inline]
 	pub fn unicode() -> Self {
-		Self { style: Style::Mono('█'), ..Default::default() }
+		Self { style: Style::Mono('█'), finish_icon: Some(('✓', '✗')), ..Default::default() }
 	}
 
 	#[inline]
 	pub fn cargo() -> Self {
 		Self { style: Style::Edged('=', '>'), ..Default::default() }
 	}
+
+	#[inline]
+	pub fn dots() -> Self {
+		Self { style: Style::Mono('·'), ..Default::default() }
+	}
+
+	#[inline]
+	pub fn arrows() -> Self {
+		Self { style: Style::Edged('-', '>'), ..Default::default() }
+	}
+
+	#[inline]
+	pub fn heavy() -> Self {
+		Self { style: Style::Edged('━', '╸'), show_delimiters: false, ..Default::default() }
+	}
+
+	#[inline]
+	pub fn braille() -> Self {
+		Self { style: Style::Mono('⣿'), space_char: '⣀', ..Default::default() }
+	}
+
+	/// Just the prefix and the percentage, e.g. `copying  42%`.
+	#[inline]
+	pub fn minimal() -> Self {
+		Self { show_elapsed: false, show_count: false, show_bar: false, show_eta: false, ..Default::default() }
+	}
+
+	/// The counts without the bar, leaving more room for the prefix and the unit, e.g.
+	/// ` 4,096 / 10,000 files  41% ETA 00:01:12`.
+	#[inline]
+	pub fn counter() -> Self {
+		Self { show_elapsed: false, show_bar: false, ..Default::default() }
+	}
+
+	/// For recording demos: every step is drawn, followed by a 20 ms pause, so that the recording shows each frame
+	/// whatever the speed of the machine it is made on.
+	#[inline]
+	pub fn demo() -> Self {
+		Self { throttle: Duration::ZERO, frame_delay: Duration::from_millis(20), ..Default::default() }
+	}
+
+	/// The presets by name, e.g. for listing them in `--help`.
+	pub fn presets() -> &'static [Preset] {
+		&[
+			("ascii", Config::ascii), ("unicode", Config::unicode), ("cargo", Config::cargo), ("dots", Config::dots),
+			("arrows", Config::arrows), ("heavy", Config::heavy), ("braille", Config::braille), ("minimal", Config::minimal),
+			("counter", Config::counter),
+		]
+	}
+
+	/// `unicode` if the terminal is known to handle it, `ascii` otherwise.
+	#[inline]
+	pub fn auto() -> Self {
+		if unicode_supported() { Self::unicode() } else { Self::ascii() }
+	}
+
+	/// Draws to the controlling terminal (`/dev/tty`, or `CONOUT$` on Windows) through `terminal`, so that the bar is
+	/// shown even when stderr is redirected. Without a controlling terminal, nothing is drawn.
+	#[cfg(feature = "terminal")]
+	pub fn tty(self) -> Self {
+		let path = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+
+		match std::fs::OpenOptions::new().read(true).write(true).open(path) {
+			Ok(file) => Self { terminal: Some(Arc::new(file)), ..self },
+			Err(_) => Self { sink: Some(Arc::new(|_| {})), ..self },
+		}
+	}
+
+	#[deprecated = "set `throttle` instead"]
+	#[inline]
+	pub fn throttle_millis(self, millis: u64) -> Self {
+		Self { throttle: Duration::from_millis(millis), ..self }
+	}
+}
+
+impl Config<'_> {
+	// The bar keeps its own copies of the labels, so that its state can be shared with the tick thread
+	fn detach(self) -> (Config<'static>, Text) {
+		let text = Text::new(&self);
+		let Self {
+			width, default_width, delimiters, style, space_char, prefix: _, unit: _, num_width, throttle, bytes, render_thread,
+			throttle_steps, eta, eta_samples, sink, on_update, color, show_rate, min_redraw_interval, max_redraw_interval,
+			rate_unit: _, countdown, show_current_rate, rounding, show_elapsed, show_count, show_bar, show_eta, hysteresis,
+			log_interval, show_delimiters, throttle_mode, log, finish_icon, bell, extra, eta_warmup, count_in_bar, terminal,
+			bar_gap, time_format, step_stats, min_bar_width, precise_seconds, hide_empty_edge, pinned, summary_to_stderr,
+			group_separator, labels, suffix: _, frame_delay, eta_range, show_percent, format_time, on_progress, on_panic, writer,
+		} = self;
+		let config = Config {
+			width, default_width, delimiters, style, space_char, prefix: "", unit: "", num_width, throttle, bytes,
+			render_thread, throttle_steps, eta, eta_samples, sink, on_update, color, show_rate, min_redraw_interval,
+			max_redraw_interval, rate_unit: "", countdown, show_current_rate, rounding, show_elapsed, show_count, show_bar,
+			show_eta, hysteresis, log_interval, show_delimiters, throttle_mode, log, finish_icon, bell, extra, eta_warmup,
+			count_in_bar, terminal, bar_gap, time_format, step_stats, min_bar_width, precise_seconds, hide_empty_edge, pinned,
+			summary_to_stderr, group_separator, labels, suffix: "", frame_delay, eta_range, show_percent, format_time,
+			on_progress, on_panic, writer,
+		};
+		(config, text)
+	}
+
+	// Fills in what the bar works out by itself: room for the counts up to `len` and the width of the terminal
+	fn resolve(mut self, len: u64) -> Self {
+		// Byte counts change suffix as they grow, so reserve room for the widest one ("1023.9 KiB")
+		self.num_width = self.num_width.max(text_width(&format_count(len, &self))).max(if self.bytes.is_some() { 10 } else { 0 });
+
+		// A writer has no size to query
+		if self.writer.is_none() {
+			self.width = line_width(self.width, self.terminal.as_deref(), |var| std::env::var(var).ok());
+		}
+
+		self
+	}
+
+	/// Checks the labels, the glyphs and the options for the problems listed in `ConfigError`, and that the segments
+	/// leave room for the bar within `width` (or `default_width`). `Bar::try_new` also takes the terminal width and the
+	/// room for the counts into account.
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		self.check()?;
+		let width = self.width.unwrap_or(self.default_width);
+		let text = Text::new(self);
+		let prefix_width = text_width(&text.prefix) as u64;
+
+		if prefix_width > width {
+			return Err(ConfigError::PrefixTooLong { width, prefix_width });
+		}
+
+		let overhead = overhead(self, &text);
+
+		if overhead > width {
+			return Err(ConfigError::TooNarrow { width, overhead });
+		}
+
+		// The edge is in the overhead
+		let bar_width = width - overhead + 1;
+
+		match self.show_bar {
+			true if bar_width < self.min_bar_width => Err(ConfigError::BarTooShort { bar_width, min_bar_width: self.min_bar_width }),
+			true if bar_width == 1 => Err(ConfigError::EmptyBar { width }),
+			_ => Ok(()),
+		}
+	}
+
+	// The problems that are there whatever the width of the terminal
+	fn check(&self) -> Result<(), ConfigError> {
+		let labels = [("prefix", self.prefix), ("suffix", self.suffix), ("unit", self.unit), ("rate_unit", self.rate_unit)];
+		let glyphs = [
+			("delimiters", self.delimiters.0), ("delimiters", self.delimiters.1), ("style", self.style.bar_char()),
+			("style", self.style.edge_char()), ("space_char", self.space_char),
+		];
+		let icons = self.finish_icon.into_iter().flat_map(|(success, failure)| [("finish_icon", success), ("finish_icon", failure)]);
+
+		// Escape sequences, e.g. coloring the prefix, are fine
+		if let Some((field, _)) = labels.into_iter().find(|(_, label)| strip_escapes(label).contains(char::is_control)) {
+			return Err(ConfigError::ControlCharacter { field });
+		}
+
+		if let Some((field, _)) = glyphs.into_iter().chain(icons).find(|(_, c)| c.is_control()) {
+			return Err(ConfigError::ControlCharacter { field });
+		}
+
+		self.labels.check()?;
+
+		if self.count_in_bar && !self.show_bar {
+			return Err(ConfigError::Requires { option: "count_in_bar", requires: "show_bar" });
+		}
+
+		Ok(())
+	}
+}
+
+/// A problem with a `Config`, from `Config::validate`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigError {
+	/// The prefix alone is wider than the line.
+	PrefixTooLong { width: u64, prefix_width: u64 },
+	/// The prefix, the suffix, the unit and the enabled segments (including the edge of the bar) take `overhead` columns.
+	TooNarrow { width: u64, overhead: u64 },
+	/// The segments fit, but leave nothing of the bar but its edge.
+	EmptyBar { width: u64 },
+	/// The segments leave fewer than `Config::min_bar_width` cells for the bar.
+	BarTooShort { bar_width: u64, min_bar_width: u64 },
+	/// A label or a glyph contains a control character, which would break the line.
+	ControlCharacter { field: &'static str },
+	/// An option that has no effect without another one.
+	Requires { option: &'static str, requires: &'static str },
+}
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			Self::PrefixTooLong { width, prefix_width } => write!(f, "the prefix takes {prefix_width} columns of a {width}-column line"),
+			Self::TooNarrow { width, overhead } => {
+				write!(f, "the segments take {overhead} columns of a {width}-column line, {} more than there are", overhead - width)
+			}
+			Self::EmptyBar { width } => write!(f, "the segments leave no room for the bar in a {width}-column line"),
+			Self::BarTooShort { bar_width, min_bar_width } => {
+				write!(f, "the segments leave {bar_width} cells for the bar, fewer than the minimum of {min_bar_width}")
+			}
+			Self::ControlCharacter { field } => write!(f, "`{field}` contains a control character"),
+			Self::Requires { option, requires } => write!(f, "`{option}` requires `{requires}`"),
+		}
+	}
 }
 
+impl std::error::Error for ConfigError {}
+
 impl Default for Config<'_> {
 	fn default() -> Self {
 		Self {
@@ -60,7 +647,55 @@ impl Default for Config<'_> {
 			prefix: "",
 			unit: "",
 			num_width: 0,
-			throttle_millis: 10,
+			throttle: Duration::from_millis(10),
+			bytes: None,
+			render_thread: false,
+			throttle_steps: None,
+			eta: EtaMode::Average,
+			eta_samples: 16,
+			sink: None,
+			on_update: None,
+			color: None,
+			show_rate: false,
+			min_redraw_interval: Duration::ZERO,
+			max_redraw_interval: None,
+			rate_unit: "",
+			countdown: false,
+			show_current_rate: false,
+			rounding: Rounding::Round,
+			show_elapsed: true,
+			show_count: true,
+			show_bar: true,
+			show_eta: true,
+			show_percent: true,
+			hysteresis: false,
+			log_interval: Duration::from_secs(30),
+			show_delimiters: true,
+			throttle_mode: ThrottleMode::Time,
+			log: None,
+			finish_icon: None,
+			bell: false,
+			extra: None,
+			eta_warmup: Duration::from_secs(1),
+			count_in_bar: false,
+			terminal: None,
+			bar_gap: 1,
+			time_format: TimeFormat::Clock,
+			step_stats: false,
+			min_bar_width: 0,
+			precise_seconds: false,
+			hide_empty_edge: false,
+			pinned: false,
+			summary_to_stderr: false,
+			group_separator: ',',
+			labels: Labels::default(),
+			suffix: "",
+			frame_delay: Duration::ZERO,
+			eta_range: false,
+			format_time: None,
+			on_progress: None,
+			on_panic: PanicBehavior::Newline,
+			writer: None,
 		}
 	}
 }
@@ -70,10 +705,62 @@ pub fn bar<I: ExactSizeIterator>(iter: I) -> impl Iterator<Item = I::Item> {
 	bar_with_config(iter, Config::default())
 }
 
+/// Takes the length from `ExactSizeIterator`, which `map`, `enumerate`, `zip`, `rev`, `skip`, `take`, `step_by`,
+/// `inspect`, `copied` and `cloned` keep, unlike `filter`, `filter_map`, `flat_map`, `flatten`, `chain`, `take_while`
+/// and `skip_while`, which can't know how many items they will let through. Use `bar_with_total` for those if the
+/// count is known, or `bar_with_hint` otherwise.
+#[inline]
+pub fn bar_with_config<I: ExactSizeIterator>(iter: I, config: Config<'_>) -> BarIter<'_, I, impl FnMut(&Bar, &I::Item)> {
+	let len = iter.len().try_into().unwrap();
+	BarIter::new(iter, Bar::new(len, config), |bar, _| bar.inc(1))
+}
+
+/// For iterators of a known length that don't implement `ExactSizeIterator`, e.g. filtered ones. Items past `total`
+/// leave the bar full, and an iterator that ends short of it leaves the final frame showing how far it got.
 #[inline]
-pub fn bar_with_config<I: ExactSizeIterator>(iter: I, config: Config) -> std::iter::Inspect<I, impl FnMut(&I::Item) + '_> {
-	let bar = Bar::new(iter.len().try_into().unwrap(), config);
-	iter.inspect(move |_| bar.inc(1))
+pub fn bar_with_total<I: Iterator>(iter: I, total: u64, config: Config<'_>) -> BarIter<'_, I, impl FnMut(&Bar, &I::Item)> {
+	BarIter::new(iter, Bar::new(total, config), |bar, _| bar.inc(1))
+}
+
+/// For any iterator: a bar if `size_hint` gives the exact length, and a spinner (see `Bar::unbounded`) otherwise.
+#[inline]
+pub fn bar_with_hint<I: Iterator>(iter: I, config: Config<'_>) -> BarIter<'_, I, impl FnMut(&Bar, &I::Item)> {
+	let bar = match iter.size_hint() {
+		(lower, Some(upper)) if lower == upper => Bar::new(lower.try_into().unwrap(), config),
+		_ => Bar::unbounded(config),
+	};
+	BarIter::new(iter, bar, |bar, _| bar.inc(1))
+}
+
+/// Advances by `size` of every item rather than by one, e.g. by the length of a chunk of bytes, towards `total`.
+#[inline]
+pub fn bar_by_size<'a, I, F>(iter: I, total: u64, config: Config<'a>, mut size: F) -> BarIter<'a, I, impl FnMut(&Bar, &I::Item)>
+	where I: Iterator, F: FnMut(&I::Item) -> u64
+{
+	BarIter::new(iter, Bar::new(total, config), move |bar, item| bar.inc(size(item)))
+}
+
+/// Shows the `Debug` representation of the current item as the message.
+#[inline]
+pub fn bar_with_debug<I: ExactSizeIterator>(iter: I, config: Config<'_>) -> BarIter<'_, I, impl FnMut(&Bar, &I::Item)>
+	where I::Item: Debug
+{
+	bar_with_label(iter, config, |item| format!("{item:?}"))
+}
+
+/// Shows `label` of the current item as the message, e.g. the path of a file. `label` is only called for the items
+/// that get drawn (for every one with `Config::render_thread`).
+#[inline]
+pub fn bar_with_label<'a, I, F>(iter: I, config: Config<'a>, mut label: F) -> BarIter<'a, I, impl FnMut(&Bar, &I::Item)>
+	where I: ExactSizeIterator, F: FnMut(&I::Item) -> String
+{
+	let len = iter.len().try_into().unwrap();
+
+	BarIter::new(iter, Bar::new(len, config), move |bar, item| {
+		bar.state.pos.add(1);
+		bar.state.record_step(1);
+		bar.state.update_with(|| bar.set_message(label(item)));
+	})
 }
 
 #[inline]
@@ -84,7 +771,7 @@ pub fn bar_chunks<T>(chunk_size: usize, slice: &[T]) -> impl Iterator<Item = &T>
 #[inline]
 pub fn bar_chunks_with_config<'b, 'a: 'b, T>(chunk_size: usize, slice: &'a [T], config: Config<'b>) -> impl Iterator<Item = &'a T> + 'b {
 	let bar = Bar::new(slice.len().try_into().unwrap(), config);
-	slice.chunks(chunk_size).inspect(move |chunk| bar.inc(chunk.len() as u64)).flatten()
+	BarIter::new(slice.chunks(chunk_size), bar, |bar, chunk| bar.inc(chunk.len() as u64)).flatten()
 }
 
 #[inline]
@@ -95,110 +782,2076 @@ pub fn bar_chunks_mut<T>(chunk_size: usize, slice: &mut [T]) -> impl Iterator<It
 #[inline]
 pub fn bar_chunks_mut_with_config<'b, 'a: 'b, T>(chunk_size: usize, slice: &'a mut [T], config: Config<'b>) -> impl Iterator<Item = &'a mut T> + 'b {
 	let bar = Bar::new(slice.len().try_into().unwrap(), config);
-	slice.chunks_mut(chunk_size).inspect(move |chunk| bar.inc(chunk.len() as u64)).flatten()
+	BarIter::new(slice.chunks_mut(chunk_size), bar, |bar, chunk| bar.inc(chunk.len() as u64)).flatten()
 }
 
-pub struct Bar<'a> {
-	config: Config<'a>,
-	len: u64,
-	pos: AtomicU64,
-	len_str: String,
-	bar_width: u64,
-	start_time: Instant,
-	last_update: AtomicU64,
+/// Wraps the iterator of a `for` loop in a bar, leaving the loop as it was otherwise, e.g.
+/// `progress!(for file in &files { ... })` or `progress!(config = Config::cargo(), for file in &files { ... })`. The
+/// iterator goes through `IntoIterator` and needs to be an `ExactSizeIterator`, unless `total = n` comes first (before
+/// `config`), which maps the loop to `bar_with_total`. The body stays in the loop, so `break`, `continue`, `return` and
+/// labels work as before.
+#[macro_export]
+macro_rules! progress {
+	(total = $total:expr, config = $config:expr, $($loop:tt)+) => {
+		$crate::progress!(@label [$crate::bar_with_total, $total, $config] $($loop)+)
+	};
+	(total = $total:expr, $($loop:tt)+) => {
+		$crate::progress!(@label [$crate::bar_with_total, $total, $crate::Config::default()] $($loop)+)
+	};
+	(config = $config:expr, $($loop:tt)+) => {
+		$crate::progress!(@label [$crate::bar_with_config, $config] $($loop)+)
+	};
+	(@label $adapter:tt $label:lifetime: for $pat:pat in $($rest:tt)+) => {
+		$crate::progress!(@split $adapter [$label:] [$pat] [] $($rest)+)
+	};
+	(@label $adapter:tt for $pat:pat in $($rest:tt)+) => {
+		$crate::progress!(@split $adapter [] [$pat] [] $($rest)+)
+	};
+	// The body is the last token tree, everything before it is the iterator
+	(@split [$adapter:path, $($args:expr),+] [$($label:tt)*] [$pat:pat] [$($iter:tt)+] { $($body:tt)* }) => {
+		$($label)* for $pat in $adapter(::core::iter::IntoIterator::into_iter($($iter)+), $($args),+) { $($body)* }
+	};
+	(@split $adapter:tt $label:tt $pat:tt [$($iter:tt)*] $next:tt $($rest:tt)+) => {
+		$crate::progress!(@split $adapter $label $pat [$($iter)* $next] $($rest)+)
+	};
+	($($loop:tt)+) => {
+		$crate::progress!(config = $crate::Config::default(), $($loop)+)
+	};
 }
 
-impl<'a> Bar<'a> {
+/// Advances a bar with the items of `iter`, from `bar_with_config` and the like. Dropped before `iter` runs out, e.g.
+/// by `break`, `?` or a panic (with `PanicBehavior::FullFrame`), it leaves the bar failed (see `Bar::set_failed`) rather
+/// than finished, so that the work left undone doesn't look like it just vanished.
+///
+/// An adapter that stops early, as in `bar(0..10).take(3)`, can't be told apart from a `break`, so it leaves the bar
+/// failed too. Limit the iterator before wrapping it, as in `bar((0..10).take(3))`, to get a bar of 3 that finishes.
+pub struct BarIter<'a, I, F> {
+	iter: I,
+	bar: Bar<'a>,
+	step: F,
+	exhausted: bool,
+}
+
+impl<'a, I: Iterator, F: FnMut(&Bar, &I::Item)> BarIter<'a, I, F> {
 	#[inline]
-	pub fn new(len: u64, mut config: Config<'a>) -> Self {
-		let len_str = format_number(len);
-		config.num_width = config.num_width.max(len_str.len());
-		#[cfg(feature = "terminal_size")]
-		{ config.width = config.width.or_else(|| Some(u64::from(terminal_size::terminal_size()?.0.0))) }
-		let bar_width = config.width.unwrap_or(config.default_width) - 35 - (config.prefix.len() + config.unit.len() + config.num_width * 2) as u64
-			- if config.unit.is_empty() { 0 } else { 1 };
-		Self { config, bar_width, len, pos: AtomicU64::new(0), len_str, start_time: Instant::now(), last_update: AtomicU64::new(0) }
+	fn new(iter: I, bar: Bar<'a>, step: F) -> Self {
+		Self { iter, bar, step, exhausted: false }
 	}
 
-	fn print(&self) -> std::io::Result<()> {
-		let mut stderr = stderr().lock();
-		let pos = self.pos.load(SeqCst);
-		assert!(pos <= self.len);
-		let ratio = (pos as f64) / (self.len as f64);
-		let progress_width = (ratio * (self.bar_width as f64)).round() as u64;
-		let secs_per_step = self.start_time.elapsed().as_secs_f64() / (pos as f64);
-		let eta = Time(((self.len.saturating_sub(pos) as f64) * secs_per_step).ceil() as u64);
-
-		write!(stderr, "\r{} {} {:>num_width$} / {:>num_width$}{}{} {}", self.config.prefix, Time(self.start_time.elapsed().as_secs()), format_number(pos),
-			self.len_str, if self.config.unit.is_empty() { "" } else { " " }, self.config.unit, self.config.delimiters.0, num_width = self.config.num_width)?;
-		write_iter(&mut stderr, std::iter::repeat(self.config.style.bar_char()).take(progress_width as usize))?;
-		write!(stderr, "{}", if pos == self.len { self.config.style.bar_char() } else { self.config.style.edge_char() })?;
-		write_iter(&mut stderr, std::iter::repeat(self.config.space_char).take((self.bar_width - progress_width) as usize))?;
-		write!(stderr, "{} {:3.0}% ETA {eta}\r", self.config.delimiters.1, ratio * 100.)?;
-		stderr.flush()?;
-		Ok(())
+	/// The bar advanced by the iterator, e.g. for `println`, `set_message` or `stats`.
+	#[inline]
+	pub fn bar(&self) -> &Bar<'a> {
+		&self.bar
 	}
+}
+
+impl<I: Iterator, F: FnMut(&Bar, &I::Item)> Iterator for BarIter<'_, I, F> {
+	type Item = I::Item;
 
 	#[inline]
-	pub fn inc(&self, delta: u64) {
-		self.pos.fetch_add(delta, SeqCst);
-		let elapsed = self.elapsed_millis();
-		let last_update = self.last_update.load(SeqCst);
+	fn next(&mut self) -> Option<I::Item> {
+		let item = self.iter.next();
 
-		if elapsed - last_update > self.config.throttle_millis && self.last_update.compare_exchange(last_update, elapsed, SeqCst, SeqCst).is_ok() {
-			self.print().unwrap();
+		match &item {
+			Some(item) => {
+				(self.step)(&self.bar, item);
+				// Consumers like `take` stop without asking for the end
+				self.exhausted = self.iter.size_hint().1 == Some(0);
+			}
+			None => self.exhausted = true,
 		}
+
+		item
 	}
 
 	#[inline]
-	pub fn finish(self) {
-		drop(self);
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.iter.size_hint()
 	}
+}
+
+impl<I: ExactSizeIterator, F: FnMut(&Bar, &I::Item)> ExactSizeIterator for BarIter<'_, I, F> {}
 
-	fn elapsed_millis(&self) -> u64 {
-		self.start_time.elapsed().as_millis().try_into().unwrap()
+impl<I, F> Drop for BarIter<'_, I, F> {
+	#[inline]
+	fn drop(&mut self) {
+		if !self.exhausted && !self.bar.state.interrupted() {
+			self.bar.state.fail().ok();
+		}
 	}
 }
 
-impl Drop for Bar<'_> {
+/// The progress of a bar at one point, from `Bar::snapshot` and `Config::on_update`, e.g. for a status endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressSnapshot {
+	pub pos: u64,
+	/// `u64::MAX` while the length isn't known, with `percent` at 0 and no ETA
+	pub len: u64,
+	pub elapsed_ms: u64,
+	/// `None` until the first step, and then until `Config::eta_warmup` is over
+	pub eta_ms: Option<u64>,
+	/// The shortest and the longest ETA with `Config::eta_range`, if the rate varies too much for `eta_ms` to mean
+	/// much. `u64::MAX` for no upper bound.
+	pub eta_range_ms: Option<(u64, u64)>,
+	/// Average steps per second
+	pub rate: f64,
+	/// Steps per second over the sample window, if `Config::show_current_rate` or an `EtaMode` other than `Average`
+	/// keeps one
+	pub current_rate: Option<f64>,
+	pub percent: f64,
+	pub prefix: String,
+	/// Set only for the final snapshot, taken when the bar is dropped
+	pub finished: bool,
+	/// The position in byte mode, otherwise the bytes reported with `Bar::inc_bytes`
+	pub bytes: u64,
+}
+
+/// A bar at one point, from `Bar::snapshot`, detached from the bar: its progress, which it dereferences to, and the
+/// line it would have drawn then, which it displays as, e.g. for logging the state at some point with
+/// `bar.snapshot().to_string()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+	pub progress: ProgressSnapshot,
+	line: String,
+}
+
+impl Deref for Snapshot {
+	type Target = ProgressSnapshot;
+
 	#[inline]
-	fn drop(&mut self) {
-		self.print().unwrap();
-		eprintln!();
+	fn deref(&self) -> &ProgressSnapshot {
+		&self.progress
 	}
 }
 
-fn write_iter<W, I>(w: &mut W, mut iter: I) -> std::io::Result<()>
-where
-	W: Write,
-	I: Iterator,
-	I::Item: Display,
-{
-	iter.try_for_each(|x| write!(w, "{x}"))
+impl Display for Snapshot {
+	#[inline]
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str(&self.line)
+	}
 }
 
-#[cfg(feature = "num-format")]
-fn format_number<T: ToFormattedStr>(number: T) -> String {
-	number.to_formatted_string(&Locale::en)
+/// How a finished bar went, e.g. for logging the time a batch job took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+	/// The position reached, if the length was never known
+	pub total: u64,
+	pub processed: u64,
+	pub elapsed: Duration,
+	/// Average steps per second
+	pub mean_rate: f64,
+	/// Set if the bar failed with `set_failed` or `abandon`
+	pub abandoned: bool,
 }
 
-#[cfg(not(feature = "num-format"))]
-fn format_number<T: Display>(number: T) -> String {
-	number.to_string()
+/// Time per step, from `Bar::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressStats {
+	pub steps: u64,
+	/// Since the start, `None` until the first step
+	pub mean_step: Option<Duration>,
+	/// The shortest and the longest time between two `inc` calls (or the start and the first one), divided by the steps
+	/// of the latter. `None` without `Config::step_stats`, or until the first step
+	pub min_step: Option<Duration>,
+	pub max_step: Option<Duration>,
+}
+
+pub struct Bar<'a> {
+	state: Arc<State>,
+	ticker: Option<Ticker>,
+	// Keeps the bar tied to the config it was created from, which lets `Phases` enforce finishing one phase before
+	// starting the next
+	_config: PhantomData<Config<'a>>,
+}
+
+struct State {
+	config: Config<'static>,
+	text: Mutex<Text>,
+	// `u64::MAX` while the length is unknown, so that the position is never clamped
+	len: AtomicU64,
+	unbounded: AtomicBool,
+	// Set when the bar turns into a spinner or back, so that the next frame clears what's left of the old one
+	relayout: AtomicBool,
+	pos: ProgressCounter,
+	// For `config.step_stats`: when `inc` was last called, and the shortest (`NEVER` before the first step) and the longest
+	// time per step, in nanoseconds
+	last_inc: AtomicU64,
+	min_step: AtomicU64,
+	max_step: AtomicU64,
+	// Where an observed counter stood when the bar was created, for `config.eta_warmup`
+	start_pos: u64,
+	bar_width: AtomicU64,
+	clock: Arc<dyn Clock>,
+	start_time: Instant,
+	last_update: AtomicU64,
+	last_update_pos: AtomicU64,
+	// `config.throttle` in nanoseconds, as it can be changed with `set_draw_rate`
+	throttle: AtomicU64,
+	samples: Mutex<Samples>,
+	finished: AtomicBool,
+	on_update_panicked: AtomicBool,
+	// The whole percentage `on_progress` was last called for
+	last_percent: AtomicU64,
+	on_progress_panicked: AtomicBool,
+	extra_panicked: AtomicBool,
+	format_time_panicked: AtomicBool,
+	// Reported by `inc_bytes` when the position counts something else
+	bytes: AtomicU64,
+	// Filled cells of the last frame, for `config.hysteresis`
+	progress_width: AtomicU64,
+	// Printing a line every `config.log_interval` instead of redrawing, and when the last one was printed (`NEVER`
+	// before the first one)
+	log_lines: bool,
+	last_log: AtomicU64,
+	// When the last line was written to `config.log`
+	last_log_write: AtomicU64,
+	// Whether the output can show colors, and `config.color` if it can
+	colors: bool,
+	color: Option<Color>,
+	// Set by `set_failed`, along with the frame shown from then on
+	failed: AtomicBool,
+	frozen: Mutex<Option<String>>,
+	row: Option<Row>,
+	// Height of the terminal for `config.pinned`, if the bar is pinned
+	pinned: Option<u64>,
 }
 
-struct Time(u64);
+struct Text {
+	prefix: String,
+	suffix: String,
+	unit: String,
+	rate_unit: String,
+	message: String,
+	// The formatted length, and the room for the counts, which grows with it
+	len: String,
+	num_width: usize,
+	// Changed with `set_style` and `set_space_char`
+	style: Style,
+	space_char: char,
+}
 
-impl Display for Time {
-	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		let hours = self.0 / 3600;
+impl Text {
+	fn new(config: &Config) -> Self {
+		Self {
+			prefix: config.prefix.to_owned(), suffix: config.suffix.to_owned(), unit: config.unit.to_owned(),
+			rate_unit: config.rate_unit.to_owned(), message: String::new(), len: String::new(), num_width: config.num_width,
+			style: config.style, space_char: config.space_char,
+		}
+	}
+
+	fn rate_unit(&self) -> &str {
+		if self.rate_unit.is_empty() { &self.unit } else { &self.rate_unit }
+	}
+}
+
+impl<'a> Bar<'a> {
+	/// `len` and the position can be anything up to `u64::MAX` (16 EiB in byte mode). Counts are displayed exactly,
+	/// while the ratio, rate and ETA are computed in `f64`, so they can't overflow, but lose precision past 2⁵³.
+	///
+	/// Panics if `config` has a problem other than not fitting the line, which is up to the terminal: a bar that
+	/// doesn't fit is cut down to its edge. Use `try_new` to check both.
+	#[inline]
+	pub fn new(len: u64, config: Config<'a>) -> Self {
+		Self::with_counter(ProgressCounter::new(), Some(len), config, None, Arc::new(SystemClock))
+	}
+
+	/// A spinner for when the length isn't known yet, e.g. before a download gets its `Content-Length`. It shows the
+	/// count and the rate, but no percentage or ETA, until `set_len` turns it into a normal bar. The spinner only moves on
+	/// redraws, so `enable_steady_tick` keeps it spinning between slow steps.
+	#[inline]
+	pub fn unbounded(config: Config<'a>) -> Self {
+		Self::with_counter(ProgressCounter::new(), None, config, None, Arc::new(SystemClock))
+	}
+
+	/// For progress that comes as a fraction, e.g. the convergence of a solver, reported with `set_fraction`. The
+	/// position counts millionths, so the counts and the rates are left out.
+	#[inline]
+	pub fn new_fraction(config: Config<'a>) -> Self {
+		let config = Config { show_count: false, count_in_bar: false, show_rate: false, show_current_rate: false, ..config };
+		Self::new(FRACTION_LEN, config)
+	}
+
+	/// A bar with the default config, showing `message` like `set_message`.
+	pub fn with_message(len: u64, message: impl Into<String>) -> Bar<'static> {
+		let bar = Bar::new(len, Config::default());
+		bar.set_message(message);
+		bar.state.print().unwrap();
+		bar
+	}
+
+	/// Like `new`, but fails if the layout leaves no room for the bar in the terminal instead of cutting the bar down to
+	/// its edge and overflowing the line.
+	pub fn try_new(len: u64, config: Config<'a>) -> Result<Self, ConfigError> {
+		let config = config.resolve(len);
+		config.validate()?;
+		Ok(Self::new(len, config))
+	}
+
+	/// Like `new`, but reads the time from `clock`. For tests.
+	#[doc(hidden)]
+	#[inline]
+	pub fn with_clock(len: u64, config: Config<'a>, clock: Arc<dyn Clock>) -> Self {
+		Self::with_counter(ProgressCounter::new(), Some(len), config, None, clock)
+	}
+
+	/// Renders `counter` from a background thread, so whoever increments the counter doesn't have to know about the
+	/// bar at all. The counter may keep changing after the bar is dropped.
+	pub fn observe(counter: ProgressCounter, len: u64, config: Config<'a>) -> Self {
+		let mut bar = Self::with_counter(counter, Some(len), config, None, Arc::new(SystemClock));
+		bar.enable_steady_tick(Duration::from_millis(100));
+		bar
+	}
+
+	fn with_counter(pos: ProgressCounter, len: Option<u64>, config: Config<'a>, row: Option<Row>, clock: Arc<dyn Clock>) -> Self {
+		if let Err(error) = config.check() {
+			panic!("{error}");
+		}
+
+		let config = config.resolve(len.unwrap_or(0));
+		let colors = use_color(&config);
+		let color = config.color.filter(|_| colors);
+		let log_lines = row.as_ref().map_or_else(|| log_lines(&config), |row| config.sink.is_none() && row.plain());
+		let pinned = config.pinned && row.is_none() && !log_lines && config.sink.is_none() && config.writer.is_none() && !line_mode() && is_terminal(&config);
+		let pinned = terminal_height(config.terminal.as_deref()).filter(|&height| pinned && height >= 2);
+		let progress_width = AtomicU64::new(if config.countdown { u64::MAX } else { 0 });
+		let (config, mut text) = config.detach();
+		text.len = len.map_or_else(String::new, |len| format_count(len, &config));
+		let bar_width = AtomicU64::new(bar_width(&config, &text));
+		let text = Mutex::new(text);
+		let samples = Mutex::new(Samples::new(config.eta_samples));
+		let throttle = AtomicU64::new(nanos(config.throttle));
+		let state = State {
+			config, text, bar_width, len: AtomicU64::new(len.unwrap_or(u64::MAX)), unbounded: AtomicBool::new(len.is_none()),
+			relayout: AtomicBool::new(false), start_pos: pos.get(), pos, last_inc: AtomicU64::new(0),
+			min_step: AtomicU64::new(NEVER), max_step: AtomicU64::new(0), start_time: clock.now(), clock, last_update: AtomicU64::new(0),
+			last_update_pos: AtomicU64::new(0), throttle, samples,
+			finished: AtomicBool::new(false), on_update_panicked: AtomicBool::new(false), last_percent: AtomicU64::new(0),
+			on_progress_panicked: AtomicBool::new(false), extra_panicked: AtomicBool::new(false),
+			format_time_panicked: AtomicBool::new(false),
+			bytes: AtomicU64::new(0), progress_width, log_lines,
+			last_log: AtomicU64::new(NEVER), last_log_write: AtomicU64::new(NEVER), colors, color,
+			failed: AtomicBool::new(false), frozen: Mutex::new(None), row, pinned,
+		};
+		// An observed counter may start anywhere
+		state.last_percent.store(state.whole_percent(state.pos.get()), SeqCst);
+
+		let mut bar = Self { state: Arc::new(state), ticker: None, _config: PhantomData };
+		bar.state.pin().unwrap();
+		// Show the bar right away rather than after the first step, which may take a while
+		bar.state.print().unwrap();
+
+		let config = &bar.state.config;
+		let throttle = Some(config.throttle.max(Duration::from_millis(1))).filter(|_| config.render_thread);
+
+		if let Some(interval) = throttle.into_iter().chain(config.max_redraw_interval).min() {
+			bar.enable_steady_tick(interval);
+		}
 
-		if hours > 99 {
-			write!(f, "??:??:??")
+		bar
+	}
+
+	/// Redraws the bar from a background thread every `interval`, in addition to the throttled redraws in `inc`.
+	pub fn enable_steady_tick(&mut self, interval: Duration) {
+		self.ticker = None;
+		self.ticker = Some(Ticker::spawn(self.state.clone(), interval, |state| {
+			state.tick().ok();
+		}));
+	}
+
+	#[inline]
+	pub fn disable_steady_tick(&mut self) {
+		self.ticker = None;
+	}
+
+	#[inline]
+	pub fn inc(&self, delta: u64) {
+		self.state.pos.add(delta);
+		self.state.record_step(delta);
+		self.state.update();
+	}
+
+	/// Like `inc`, but returns the position this very step got to, even with other threads stepping too, e.g. for
+	/// treating every 100th item specially. The position saturates at `u64::MAX` and can go past `len`, like with
+	/// `inc`.
+	#[inline]
+	pub fn inc_and_get(&self, delta: u64) -> u64 {
+		let pos = self.state.pos.add_and_get(delta);
+		self.state.record_step(delta);
+		self.state.update();
+		pos
+	}
+
+	/// Like `inc`, but stops at `len` and finishes the bar when it gets there, drawing the final frame right away.
+	#[inline]
+	pub fn inc_saturating(&self, delta: u64) {
+		self.state.pos.add(delta);
+		self.state.record_step(delta);
+
+		let len = self.state.len();
+
+		if self.state.pos.get() >= len {
+			self.state.pos.set(len);
+			self.state.finish().unwrap();
 		} else {
-			let mins = (self.0 / 60) % 60;
-			let secs = self.0 % 60;
-			write!(f, "{hours:02}:{mins:02}:{secs:02}")
+			self.state.update();
+		}
+	}
+
+	/// Advances the position to `pos`, for counters that only grow. A lower `pos` is ignored.
+	#[inline]
+	pub fn inc_to(&self, pos: u64) {
+		self.state.pos.inc_to(pos);
+		self.state.update();
+	}
+
+	/// Moves the position to `pos`, backwards too, e.g. when a transfer restarts, and returns the previous one. Going
+	/// backwards starts `Config::hysteresis` over.
+	#[inline]
+	pub fn set_position(&self, pos: u64) -> u64 {
+		let previous = self.state.pos.replace(pos);
+
+		if pos < previous {
+			self.state.reset_progress_width();
+		}
+
+		self.state.update();
+		previous
+	}
+
+	/// Moves the position to `fraction` of the length, for a bar from `new_fraction` or any other of a known length.
+	/// The fraction is clamped to `0.0..=1.0`, and NaN counts as 0.
+	#[inline]
+	pub fn set_fraction(&self, fraction: f64) {
+		let fraction = if fraction.is_nan() { 0. } else { fraction.clamp(0., 1.) };
+		self.set_position((fraction * self.state.len() as f64).round() as u64);
+	}
+
+	/// Changes `Config::throttle`, e.g. to redraw less often during a phase of heavy computation. The interval of a
+	/// `render_thread` stays as it was.
+	#[inline]
+	pub fn set_draw_rate(&self, throttle: Duration) {
+		self.state.throttle.store(nanos(throttle), SeqCst);
+	}
+
+	/// Whether `inc` would redraw the bar now, for driving the drawing separately from the counting: `true` counts as
+	/// a redraw for throttling, so follow it with `refresh`. Always `false` for a failed bar or with `render_thread`.
+	#[inline]
+	pub fn should_redraw(&self) -> bool {
+		!self.state.failed.load(SeqCst) && !self.state.config.render_thread && self.state.due()
+	}
+
+	/// Redraws the bar now, regardless of throttling.
+	#[inline]
+	pub fn refresh(&self) {
+		self.state.print().unwrap();
+	}
+
+	/// Draws the bar now, e.g. right after `set_message` or at a milestone, rather than at the next redraw `inc` lets
+	/// through. It counts as one, so the throttling goes on from there. Does nothing for a failed or finished bar, and
+	/// lines printed instead of redrawn (see `Config::log_interval`) still come at their interval.
+	pub fn draw_now(&self) -> std::io::Result<()> {
+		if self.state.failed.load(SeqCst) {
+			return Ok(());
+		}
+
+		self.state.draw()
+	}
+
+	/// The line for an area `width` columns wide, e.g. a line of a TUI, without drawing anything or affecting the frames
+	/// of the bar, i.e. its `hysteresis` and rates. Colors are only there if the bar draws to a terminal that shows them.
+	/// A layout that doesn't fit even with the bar cut down to its edge is cut short, without colors.
+	pub fn render(&self, width: u16) -> String {
+		let line = self.state.render_width(Some(u64::from(width))).1;
+
+		match strip_escapes(&line) {
+			text if text_width(&text) > usize::from(width) => truncate(&text, width.into()).into_owned(),
+			_ => line,
+		}
+	}
+
+	/// Replaces the prefix, growing or shrinking the bar to keep the line width. A prefix too long to leave any room
+	/// leaves just the edge of the bar.
+	pub fn set_prefix(&self, prefix: impl Into<String>) {
+		self.state.update_text(|text| text.prefix = prefix.into());
+	}
+
+	/// Replaces the suffix, resizing the bar like `set_prefix`.
+	pub fn set_suffix(&self, suffix: impl Into<String>) {
+		self.state.update_text(|text| text.suffix = suffix.into());
+	}
+
+	/// Replaces the glyphs of the bar from the next frame on, e.g. for showing a retrying state, which is drawn over
+	/// the whole line in case the glyphs are of another width.
+	pub fn set_style(&self, style: Style) {
+		self.state.text.lock().unwrap().style = style;
+		self.state.relayout.store(true, SeqCst);
+	}
+
+	/// Replaces `Config::space_char` like `set_style`.
+	pub fn set_space_char(&self, space_char: char) {
+		self.state.text.lock().unwrap().space_char = space_char;
+		self.state.relayout.store(true, SeqCst);
+	}
+
+	/// Replaces the unit, resizing the bar like `set_prefix`.
+	pub fn set_unit(&self, unit: impl Into<String>) {
+		self.state.update_text(|text| text.unit = unit.into());
+	}
+
+	/// Shows `message` after the prefix, shrinking the bar to make room for it. A message longer than the bar is
+	/// truncated. It is drawn with the next redraw, or right away with `draw_now`.
+	pub fn set_message(&self, message: impl Into<String>) {
+		self.state.update_text(|text| text.message = message.into());
+	}
+
+	/// Sets the length, e.g. once a download gets its `Content-Length`. The position and the elapsed time carry over, so
+	/// the ETA comes from the rate so far right away. A spinner from `unbounded` turns into a bar.
+	///
+	/// A length below the position shows a full bar. The position itself is left alone, as it may be an observed
+	/// counter, so a longer length set later shows it again.
+	pub fn set_len(&self, len: u64) {
+		self.state.set_len(Some(len));
+	}
+
+	/// Forgets the length, turning the bar into a spinner like one from `unbounded`.
+	pub fn clear_len(&self) {
+		self.state.set_len(None);
+	}
+
+	/// The time per step so far.
+	pub fn stats(&self) -> ProgressStats {
+		let steps = self.state.pos.get().min(self.state.len());
+		let elapsed = self.state.clock.now().saturating_duration_since(self.state.start_time);
+		let (min_step, max_step) = (self.state.min_step.load(SeqCst), self.state.max_step.load(SeqCst));
+		let recorded = min_step != NEVER;
+
+		ProgressStats {
+			steps,
+			mean_step: (steps > 0).then(|| Duration::from_secs_f64(elapsed.as_secs_f64() / steps as f64)),
+			min_step: recorded.then(|| Duration::from_nanos(min_step)),
+			max_step: recorded.then(|| Duration::from_nanos(max_step)),
+		}
+	}
+
+	/// The current progress, all derived from a single read of the position, along with the line as it would be drawn
+	/// now, without drawing it.
+	pub fn snapshot(&self) -> Snapshot {
+		let (progress, line) = self.state.render();
+		let line = self.state.frozen.lock().unwrap().clone().unwrap_or(line);
+		Snapshot { progress, line }
+	}
+
+	/// Prints a line above the bar without breaking it.
+	pub fn println(&self, message: impl Display) {
+		self.suspend(|| eprintln!("{message}"));
+	}
+
+	/// Clears the bar, runs `f`, which can write to stderr freely, and redraws the bar below its output. For a bar in
+	/// a `MultiBar`, this applies to the whole block, and `f` must not update bars of the same block.
+	pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+		if let Some(row) = &self.state.row {
+			return row.suspend(f);
+		}
+
+		// A logged line stays as it is, and frames that go to a sink aren't on the terminal at all
+		if !self.state.log_lines && self.state.config.sink.is_none() {
+			clear_lines(&mut self.state.terminal(), 0).unwrap();
+		}
+
+		let result = f();
+		self.state.print().unwrap();
+		result
+	}
+
+	/// Same as `inc` for bars that count bytes (`config.bytes` is set). Bars that count something else show the total
+	/// of `n` next to the count once it is non-zero, e.g. `3,200 / 10,000 rows · 1.2 GiB`.
+	#[inline]
+	pub fn inc_bytes(&self, n: u64) {
+		if self.state.config.bytes.is_some() {
+			self.inc(n);
+		} else {
+			self.state.bytes.fetch_add(n, SeqCst);
+			self.state.update();
+		}
+	}
+
+	/// The position in byte mode, otherwise the total reported with `inc_bytes`.
+	#[inline]
+	pub fn bytes(&self) -> u64 {
+		// Like the snapshot, without taking one, which would add to the samples of the rates
+		match self.state.config.bytes {
+			Some(_) => self.state.pos.get().min(self.state.len()),
+			None => self.state.bytes.load(SeqCst),
+		}
+	}
+
+	/// Draws the final frame, like dropping the bar, and returns how it went.
+	pub fn finish(mut self) -> Summary {
+		self.ticker = None;
+		self.state.finish().unwrap();
+		self.state.summary()
+	}
+
+	/// Finishes the bar and erases it, leaving the cursor at the start of the empty line. A bar in a `MultiBar` loses
+	/// its row. Nothing is erased when the frames aren't redrawn in place, e.g. when they go to `Config::sink`.
+	pub fn finish_and_clear(mut self) {
+		self.ticker = None;
+		self.state.clear().unwrap();
+	}
+
+	/// Finishes a bar in a `MultiBar` and removes its row like `finish_and_clear`, but through a shared reference, e.g.
+	/// from a worker done with a bar that other threads may still be stepping, which is then no longer drawn. The rows
+	/// below move up. Does nothing for a bar that isn't in a `MultiBar`.
+	pub fn remove_row(&self) {
+		if self.state.row.is_some() {
+			self.state.clear().unwrap();
+		}
+	}
+
+	/// Finishes the bar as failed.
+	#[inline]
+	pub fn abandon(self) {
+		self.set_failed();
+	}
+
+	/// Freezes the bar in a failed state: the ETA is replaced with the time it failed after, and the bar turns red if
+	/// colors are enabled, or `!!` is shown before the percentage otherwise. Later steps aren't shown.
+	pub fn set_failed(&self) {
+		self.state.fail().unwrap();
+	}
+}
+
+impl Drop for Bar<'_> {
+	#[inline]
+	fn drop(&mut self) {
+		self.ticker = None;
+
+		// A write error would abort the process during a panic, and is of no use to anyone otherwise
+		if self.state.interrupted() {
+			self.state.interrupt().ok();
+		} else {
+			self.state.finish().ok();
+		}
+	}
+}
+
+impl State {
+	fn print(&self) -> std::io::Result<()> {
+		// The final frame stays, e.g. after `inc_saturating`
+		if self.finished.load(SeqCst) {
+			return Ok(());
+		}
+
+		let line = self.frame();
+		self.output(line, false)
+	}
+
+	fn fail(&self) -> std::io::Result<()> {
+		if self.finished.load(SeqCst) || self.failed.swap(true, SeqCst) {
+			return Ok(());
+		}
+
+		let line = self.frame();
+		*self.frozen.lock().unwrap() = Some(line.clone());
+		self.output(line, false)
+	}
+
+	// Whether the bar is being dropped by a panic and shouldn't draw its final frame
+	fn interrupted(&self) -> bool {
+		thread::panicking() && self.config.on_panic != PanicBehavior::FullFrame
+	}
+
+	// Gives the line up to the panic message instead of drawing the final frame. The block of a `MultiBar` already
+	// leaves the cursor below it, and the other outputs don't have a cursor
+	fn interrupt(&self) -> std::io::Result<()> {
+		if self.finished.swap(true, SeqCst) {
+			return Ok(());
+		}
+
+		self.report_final();
+
+		if self.row.is_some() || self.config.sink.is_some() || self.log_lines {
+			return Ok(());
+		}
+
+		if self.pinned.is_some() {
+			return self.unpin();
+		}
+
+		if self.config.on_panic == PanicBehavior::Newline && !line_mode() {
+			let mut terminal = self.terminal();
+			writeln!(terminal)?;
+			terminal.flush()?;
+		}
+
+		Ok(())
+	}
+
+	// Draws the final frame, once
+	fn finish(&self) -> std::io::Result<()> {
+		if self.finished.swap(true, SeqCst) {
+			return Ok(());
+		}
+
+		// E.g. `inc_saturating` gets to the end without an update
+		self.report_progress();
+		let line = self.frame();
+
+		if self.config.summary_to_stderr && self.config.terminal.is_some() {
+			eprintln!("{}", strip_escapes(&line).trim_start());
+		}
+
+		self.output(line, true)?;
+
+		if self.config.bell && self.config.sink.is_none() && is_terminal(&self.config) {
+			let mut terminal = self.terminal();
+			terminal.write_all(b"\x07")?;
+			terminal.flush()?;
+		}
+
+		Ok(())
+	}
+
+	#[inline]
+	fn record_step(&self, delta: u64) {
+		if !self.config.step_stats || delta == 0 {
+			return;
+		}
+
+		let now = self.elapsed_nanos();
+		let step = now.saturating_sub(self.last_inc.swap(now, SeqCst)) / delta;
+		self.min_step.fetch_min(step, SeqCst);
+		self.max_step.fetch_max(step, SeqCst);
+	}
+
+	// Finishes the bar without a final frame, erasing the last one
+	fn clear(&self) -> std::io::Result<()> {
+		if self.finished.swap(true, SeqCst) {
+			return Ok(());
+		}
+
+		self.report_final();
+
+		if let Some(row) = &self.row {
+			return row.remove(self.row_progress());
+		}
+
+		if self.config.sink.is_some() || self.log_lines {
+			return Ok(());
+		}
+
+		if self.pinned.is_some() {
+			return self.unpin();
+		}
+
+		let mut terminal = self.terminal();
+		clear_lines(&mut terminal, 0)?;
+		terminal.flush()
+	}
+
+	// Makes room for the bar below the cursor and keeps everything else from scrolling into the bottom row. Setting the
+	// scroll region moves the cursor home, so it is saved and restored around it
+	fn pin(&self) -> std::io::Result<()> {
+		let Some(height) = self.pinned else { return Ok(()) };
+		let mut terminal = self.terminal();
+		write!(terminal, "\n\x1b[1A\x1b7\x1b[1;{}r\x1b8", height - 1)?;
+		terminal.flush()
+	}
+
+	// Gives the bottom row back, blank
+	fn unpin(&self) -> std::io::Result<()> {
+		let Some(height) = self.pinned else { return Ok(()) };
+		let mut terminal = self.terminal();
+		write!(terminal, "\x1b7\x1b[r\x1b[{height};1H\x1b[2K\x1b8")?;
+		terminal.flush()
+	}
+
+	fn frame(&self) -> String {
+		let (snapshot, line) = self.render();
+		self.report(&snapshot);
+		self.frozen.lock().unwrap().clone().unwrap_or(line)
+	}
+
+	// Called without holding any locks, so that the callback can't deadlock the bar. A panicking callback is disabled
+	// rather than taking the bar down with it
+	fn report(&self, snapshot: &ProgressSnapshot) {
+		if let Some(on_update) = &self.config.on_update {
+			if !self.on_update_panicked.load(SeqCst) && panic::catch_unwind(AssertUnwindSafe(|| on_update(snapshot))).is_err() {
+				self.on_update_panicked.store(true, SeqCst);
+			}
+		}
+	}
+
+	// The final call of `on_update` for a bar that finishes without drawing a final frame
+	fn report_final(&self) {
+		if self.config.on_update.is_some() {
+			let snapshot = self.snapshot(&self.text.lock().unwrap().prefix, true);
+			self.report(&snapshot);
+		}
+	}
+
+	fn output(&self, line: String, last: bool) -> std::io::Result<()> {
+		if let Some(log) = &self.config.log {
+			if self.log_due(&self.last_log_write, last) {
+				// Colors are for the terminal only
+				writeln!(log.lock().unwrap(), "{}", strip_escapes(&line).trim_start()).ok();
+			}
+		}
+
+		if let Some(sink) = &self.config.sink {
+			sink(&line);
+			return Ok(());
+		}
+
+		if let Some(row) = &self.row {
+			// The block isn't drawn off a terminal, so its frames are logged like those of a single bar
+			if self.log_lines && self.log_due(&self.last_log, last) {
+				row.log(&line)?;
+			}
+
+			let progress = self.row_progress();
+			return if last { row.finish(line, progress) } else { row.update(line, progress) };
+		}
+
+		if let Some(height) = self.pinned {
+			if !last {
+				let mut terminal = self.terminal();
+				write!(terminal, "\x1b7\x1b[{height};1H\x1b[2K{line}\x1b8")?;
+				return terminal.flush();
+			}
+
+			// The final frame stays where the output has got to, like that of a bar that isn't pinned
+			self.unpin()?;
+		}
+
+		let mut terminal = self.terminal();
+
+		if self.log_lines {
+			if !self.log_due(&self.last_log, last) {
+				return Ok(());
+			}
+
+			return writeln!(terminal, "{line}");
+		}
+
+		// A line of another shape doesn't cover all of the last one
+		let clear = if self.relayout.swap(false, SeqCst) && !line_mode() { "\x1b[2K" } else { "" };
+
+		if line_mode() || last {
+			writeln!(terminal, "\r{clear}{line}")?;
+		} else {
+			write!(terminal, "\r{clear}{line}\r")?;
+		}
+
+		terminal.flush()
+	}
+
+	// For the total of a `MultiBar`, to which a bar of unknown length adds nothing
+	fn row_progress(&self) -> (u64, u64) {
+		let len = self.len();
+		if self.unbounded.load(SeqCst) { (0, 0) } else { (self.pos.get().min(len), len) }
+	}
+
+	fn summary(&self) -> Summary {
+		let elapsed = self.clock.now().saturating_duration_since(self.start_time);
+		let len = self.len();
+		let processed = self.pos.get().min(len);
+		let secs = elapsed.as_secs_f64();
+
+		Summary {
+			total: if self.unbounded.load(SeqCst) { processed } else { len },
+			processed,
+			elapsed,
+			mean_rate: if secs > 0. { processed as f64 / secs } else { 0. },
+			abandoned: self.failed.load(SeqCst),
+		}
+	}
+
+	fn terminal(&self) -> Terminal<'_> {
+		match (&self.config.writer, &self.config.terminal) {
+			(Some(writer), _) => Terminal::writer(writer),
+			(None, Some(file)) => Terminal::File(file),
+			(None, None) => Terminal::Stderr(terminal()),
+		}
+	}
+
+	// `sample` adds the position to the samples of the rates, which a mere look at the bar doesn't
+	fn snapshot(&self, prefix: &str, sample: bool) -> ProgressSnapshot {
+		// Everything is derived from a single read of the position and the length. An observed counter can run past `len`
+		let len = self.len();
+		let unbounded = self.unbounded.load(SeqCst);
+		let pos = self.pos.get().min(len);
+		let elapsed = self.clock.now().saturating_duration_since(self.start_time);
+		let secs = elapsed.as_secs_f64();
+
+		// Both come from the same window, which isn't kept unless something needs it
+		let (current_rate, linear_rate, spread) = if self.config.eta != EtaMode::Average || self.config.show_current_rate || self.config.eta_range {
+			let mut samples = self.samples.lock().unwrap();
+
+			if sample {
+				samples.push(secs, pos);
+			}
+
+			(
+				samples.recent_rate(),
+				if self.config.eta == EtaMode::Linear { samples.linear_rate() } else { None },
+				if self.config.eta_range { samples.rate_spread() } else { None },
+			)
+		} else {
+			(None, None, None)
+		};
+
+		// There is nothing to extrapolate from until the first step, and too little until the warmup is over
+		let eta = match pos {
+			_ if unbounded => None,
+			_ if pos == len => Some(0.),
+			0 => None,
+			_ if self.warming_up(pos, elapsed) => None,
+			_ => Some(self.eta_secs(pos, len, secs, if self.config.eta == EtaMode::Linear { linear_rate } else { current_rate })),
+		};
+		let eta_range = match (eta, spread) {
+			(Some(eta), Some((mean, deviation))) if eta > 0. && deviation > mean * ETA_RANGE_DEVIATION => {
+				let remaining = (len - pos) as f64;
+				// Infinite, and so `u64::MAX`, when the rate may drop to nothing
+				let high = if mean > deviation { remaining / (mean - deviation) } else { f64::INFINITY };
+				Some(((remaining / (mean + deviation) * 1000.).ceil() as u64, (high * 1000.).ceil() as u64))
+			}
+			_ => None,
+		};
+
+		ProgressSnapshot {
+			pos,
+			len,
+			elapsed_ms: elapsed.as_millis().try_into().unwrap_or(u64::MAX),
+			eta_ms: eta.map(|eta| (eta * 1000.).ceil() as u64),
+			eta_range_ms: eta_range,
+			rate: if secs > 0. { pos as f64 / secs } else { 0. },
+			current_rate,
+			percent: match len {
+				_ if unbounded => 0.,
+				0 => 100.,
+				_ => pos as f64 / len as f64 * 100.,
+			},
+			prefix: prefix.to_owned(),
+			finished: self.finished.load(SeqCst),
+			bytes: if self.config.bytes.is_some() { pos } else { self.bytes.load(SeqCst) },
+		}
+	}
+
+	#[inline]
+	fn render(&self) -> (ProgressSnapshot, String) {
+		self.render_width(None)
+	}
+
+	// Lays the line out for `width` columns instead of the width of the bar
+	fn render_width(&self, width: Option<u64>) -> (ProgressSnapshot, String) {
+		// `set_len` changes both under the lock
+		let (snapshot, unbounded) = {
+			let text = self.text.lock().unwrap();
+			(self.snapshot(&text.prefix, width.is_none()), self.unbounded.load(SeqCst))
+		};
+		// Like `on_update`, called without holding any locks
+		let extra = match &self.config.extra {
+			Some(extra) if !self.extra_panicked.load(SeqCst) => panic::catch_unwind(AssertUnwindSafe(|| extra(&snapshot))).unwrap_or_else(|_| {
+				self.extra_panicked.store(true, SeqCst);
+				String::new()
+			}),
+			_ => String::new(),
+		};
+		let failed = self.failed.load(SeqCst);
+		// So is `Config::format_time`
+		let elapsed = match self.config.show_elapsed || self.config.show_eta && failed {
+			true => self.format_time(self.time(snapshot.elapsed_ms), TimeField::Elapsed),
+			false => String::new(),
+		};
+		let eta = if self.config.show_eta && !unbounded { self.eta(&snapshot, failed, &elapsed) } else { String::new() };
+		let text = self.text.lock().unwrap();
+		let pos = snapshot.pos;
+		// Shown only once there are bytes to show, so it's only then that the bar makes room for it
+		let bytes = Some(snapshot.bytes).filter(|&bytes| bytes > 0 && self.config.bytes.is_none() && self.config.show_count);
+		let bar_width = width.map_or_else(|| self.bar_width.load(SeqCst), |width| width.saturating_sub(overhead(&self.config, &text)));
+		let mut bar_width = bar_width.saturating_sub(if bytes.is_some() { BYTES_SEGMENT_WIDTH } else { 0 });
+
+		// "failed after" is longer than "ETA", and `!!` takes the place of the red
+		if failed {
+			let labels = &self.config.labels;
+			let failed_width = if self.config.show_eta { label_width(&labels.failed).saturating_sub(label_width(&labels.eta)) } else { 0 };
+			bar_width = bar_width.saturating_sub(failed_width as u64 + if self.colors { 0 } else { 3 });
+		}
+
+		// Wide glyphs take two cells each, the edge included
+		let (style, space_char) = (text.style, text.space_char);
+		let glyph_width = [style.bar_char(), style.edge_char(), space_char]
+			.map(char_width).into_iter().max().unwrap().max(1) as u64;
+		// The message changes too often to be accounted for in advance. One too long for the line is cut short rather
+		// than wrapped
+		let message = truncate(&text.message, bar_width.saturating_sub(glyph_width) as usize);
+
+		if !message.is_empty() {
+			bar_width -= 1 + text_width(&message) as u64;
+		}
+
+		let extra = truncate(&extra, bar_width.saturating_sub(glyph_width) as usize);
+
+		if !extra.is_empty() {
+			bar_width -= 1 + text_width(&extra) as u64;
+		}
+
+		let (shown, ratio) = if self.config.countdown {
+			(snapshot.len - pos, 1. - snapshot.percent / 100.)
+		} else {
+			(pos, snapshot.percent / 100.)
+		};
+		// An odd cell left over is padded after the bar
+		let padding = (bar_width + 1) % glyph_width;
+		let bar_width = ((bar_width + 1) / glyph_width).saturating_sub(1);
+		// Integer math, as f64 can't tell the last steps of a huge `len` apart
+		let progress_width = match snapshot.len {
+			0 => if self.config.countdown { 0 } else { bar_width },
+			len => {
+				let (filled, len) = (u128::from(shown) * u128::from(bar_width), u128::from(len));
+
+				(match self.config.rounding {
+					Rounding::Round => (filled * 2 + len) / (len * 2),
+					Rounding::Floor => filled / len,
+					Rounding::Ceil => filled.div_ceil(len),
+				}) as u64
+			}
+		};
+		// Only the frames of the bar itself hold on to their progress, not those rendered at another width
+		let progress_width = match self.config.hysteresis && width.is_none() {
+			false => progress_width,
+			true if self.config.countdown => self.progress_width.fetch_min(progress_width, SeqCst).min(progress_width),
+			true => self.progress_width.fetch_max(progress_width, SeqCst).max(progress_width).min(bar_width),
+		};
+		let icon = self.config.finish_icon.filter(|_| snapshot.finished || failed).map(|(success, failure)| {
+			if failed { (failure, Color::Red) } else { (success, Color::Green) }
+		});
+		let fill_color = if failed && self.colors { Some(Color::Red) } else { self.color };
+		let mut line = String::new();
+
+		line.push_str(&text.prefix);
+
+		if !message.is_empty() {
+			write!(line, " {message}").unwrap();
+		}
+
+		if self.config.show_elapsed {
+			push_label(&mut line, &self.config.labels.elapsed);
+			write!(line, " {elapsed}").unwrap();
+		}
+
+		if self.config.show_count {
+			push_label(&mut line, self.config.labels.count(self.config.countdown));
+		}
+
+		if self.config.show_count && unbounded {
+			write!(line, " {}{}{}", format_count(pos, &self.config), if text.unit.is_empty() { "" } else { " " }, text.unit).unwrap();
+		} else if self.config.show_count {
+			write!(line, " {:>num_width$}{}{:>num_width$}{}{}", format_count(shown, &self.config), self.config.labels.separator,
+				text.len, if text.unit.is_empty() { "" } else { " " }, text.unit, num_width = text.num_width).unwrap();
+
+			if let Some(bytes) = bytes {
+				write!(line, " · {:>10}", ByteUnits::Binary.format(bytes)).unwrap();
+			}
+		}
+
+		if self.config.show_bar && unbounded {
+			line.extend(std::iter::repeat_n(' ', self.config.bar_gap));
+			line.push_str(fill_color.map_or("", Color::sgr));
+
+			match icon {
+				Some((icon, color)) => write!(line, "{}{icon}", if self.colors { color.sgr() } else { "" }).unwrap(),
+				None if snapshot.finished => line.push(style.bar_char()),
+				None => line.push(SPINNER[(snapshot.elapsed_ms / 100 % SPINNER.len() as u64) as usize]),
+			}
+
+			line.push_str(if fill_color.is_some() || (icon.is_some() && self.colors) { "\x1b[0m" } else { "" });
+		} else if self.config.show_bar {
+			line.extend(std::iter::repeat_n(' ', self.config.bar_gap));
+
+			if self.config.show_delimiters {
+				line.push(self.config.delimiters.0);
+			}
+
+			// The count takes whole glyphs in the middle of the bar, padded with spaces to fill the last one, and the
+			// glyphs around it show through
+			let overlay: Vec<char> = match self.config.count_in_bar {
+				true => format!("{}/{}", format_count(shown, &self.config), text.len).chars().collect(),
+				false => Vec::new(),
+			};
+			let overlay = if (overlay.len() as u64).div_ceil(glyph_width) <= bar_width + 1 { overlay } else { Vec::new() };
+			let overlay_start = (bar_width + 1 - (overlay.len() as u64).div_ceil(glyph_width)) / 2;
+			let push_glyph = |line: &mut String, glyph: u64, c: char| {
+				match glyph.checked_sub(overlay_start).map(|i| (i * glyph_width) as usize).filter(|&i| i < overlay.len()) {
+					Some(i) => line.extend((i..i + glyph_width as usize).map(|i| overlay.get(i).copied().unwrap_or(' '))),
+					None => line.push(c),
+				}
+			};
+
+			line.push_str(fill_color.map_or("", Color::sgr));
+			(0..progress_width).for_each(|glyph| push_glyph(&mut line, glyph, style.bar_char()));
+
+			match icon {
+				Some((icon, color)) => {
+					line.push_str(if self.colors { color.sgr() } else { "" });
+					push_glyph(&mut line, progress_width, icon);
+				}
+				None => push_glyph(&mut line, progress_width, match shown {
+					// An emptied countdown bar
+					0 if self.config.countdown => space_char,
+					_ if shown == snapshot.len => style.bar_char(),
+					_ if progress_width == 0 && self.config.hide_empty_edge => space_char,
+					_ => style.edge_char(),
+				}),
+			}
+
+			line.push_str(if fill_color.is_some() || (icon.is_some() && self.colors) { "\x1b[0m" } else { "" });
+			(progress_width + 1..=bar_width).for_each(|glyph| push_glyph(&mut line, glyph, space_char));
+
+			if self.config.show_delimiters {
+				line.push(self.config.delimiters.1);
+			}
+
+			line.extend(std::iter::repeat_n(' ', padding as usize));
+		}
+
+		if !self.config.show_bar && self.config.finish_icon.is_some() {
+			match icon {
+				Some((icon, color)) if self.colors => write!(line, " {}{icon}\x1b[0m", color.sgr()).unwrap(),
+				Some((icon, _)) => write!(line, " {icon}").unwrap(),
+				None => line.push_str("  "),
+			}
+		}
+
+		if failed && !self.colors {
+			line.push_str(" !!");
+		}
+
+		// There is nothing to show a percentage or an ETA of
+		if self.config.show_percent && !unbounded {
+			write!(line, " {:3.0}%", ratio * 100.).unwrap();
+		}
+
+		if self.config.show_eta && !unbounded {
+			push_label(&mut line, if failed { &self.config.labels.failed } else { &self.config.labels.eta });
+			// Padded to the room for a range
+			write!(line, " {eta:<width$}", width = eta_width(&self.config)).unwrap();
+		}
+
+		if self.config.show_rate || self.config.show_current_rate {
+			let width = rate_width(&self.config, &text);
+			let warming_up = self.warming_up(pos, Duration::from_millis(snapshot.elapsed_ms));
+			let format = |rate: Option<f64>| match rate {
+				Some(rate) if !warming_up => format_rate(rate, &self.config, text.rate_unit(), rate_number_width(&text)),
+				_ => "--".to_owned(),
+			};
+			let (average, current) = (format(Some(snapshot.rate)), format(snapshot.current_rate));
+
+			match (self.config.show_rate, self.config.show_current_rate) {
+				(true, true) => {
+					push_label(&mut line, &self.config.labels.average);
+					write!(line, " {average:>width$} ·").unwrap();
+					push_label(&mut line, &self.config.labels.current);
+					write!(line, " {current:>width$}").unwrap();
+				}
+				(true, false) => write!(line, " {average:>width$}").unwrap(),
+				_ => write!(line, " {current:>width$}").unwrap(),
+			}
+		}
+
+		if !text.suffix.is_empty() {
+			write!(line, " {}", text.suffix).unwrap();
+		}
+
+		if !extra.is_empty() {
+			write!(line, " {extra}").unwrap();
+		}
+
+		(snapshot, line)
+	}
+
+	fn time(&self, millis: u64) -> Time {
+		Time { millis, format: self.config.time_format, precise: self.config.precise_seconds }
+	}
+
+	// The time that a failed bar shows in place of the ETA is `elapsed`
+	fn eta(&self, snapshot: &ProgressSnapshot, failed: bool, elapsed: &str) -> String {
+		let unknown = match &self.config.format_time {
+			Some((_, width)) => format!("{:>width$}", "--"),
+			None => self.config.time_format.unknown().to_owned(),
+		};
+		let eta = |millis| self.format_time(self.time(millis).ceil(), TimeField::Eta);
+
+		match (snapshot.eta_ms, snapshot.eta_range_ms) {
+			_ if failed => elapsed.to_owned(),
+			(_, Some((low, u64::MAX))) => format!("{}–{unknown}", eta(low)),
+			(_, Some((low, high))) => format!("{}–{}", eta(low), eta(high)),
+			(Some(eta_ms), None) => eta(eta_ms),
+			(None, None) => unknown,
+		}
+	}
+
+	fn format_time(&self, time: Time, field: TimeField) -> String {
+		let Some((format, width)) = &self.config.format_time else { return time.to_string() };
+		// Like `extra`, a formatter that has panicked isn't called again, and the built-in format takes its place
+		let text = Some(format).filter(|_| !self.format_time_panicked.load(SeqCst))
+			.and_then(|format| panic::catch_unwind(AssertUnwindSafe(|| format(Duration::from_millis(time.millis), field))).ok())
+			.unwrap_or_else(|| {
+				self.format_time_panicked.store(true, SeqCst);
+				time.to_string()
+			});
+		let text = truncate(&text, *width);
+		format!("{}{text}", " ".repeat(width.saturating_sub(text_width(&text))))
+	}
+
+	// `rate` is the windowed estimate for the ETA mode
+	fn eta_secs(&self, pos: u64, len: u64, elapsed: f64, rate: Option<f64>) -> f64 {
+		let remaining = (len - pos) as f64;
+		let average = remaining * elapsed / pos as f64;
+
+		if self.config.eta == EtaMode::Average {
+			return average;
+		}
+
+		// Too few samples, or no progress within them
+		rate.map(|rate| remaining / rate).filter(|eta| eta.is_finite() && *eta >= 0.).unwrap_or(average)
+	}
+
+	fn set_len(&self, len: Option<u64>) {
+		self.store_len(len);
+		self.print().unwrap();
+	}
+
+	// The length and the layout that depends on it, without drawing a frame
+	fn store_len(&self, len: Option<u64>) {
+		let mut text = self.text.lock().unwrap();
+		text.len = len.map_or_else(String::new, |len| format_count(len, &self.config));
+		// The counts only ever get more room, so that they don't jump back and forth
+		text.num_width = text.num_width.max(text_width(&text.len));
+		self.len.store(len.unwrap_or(u64::MAX), SeqCst);
+
+		if self.unbounded.swap(len.is_none(), SeqCst) != len.is_none() {
+			self.relayout.store(true, SeqCst);
+		}
+
+		self.reset_progress_width();
+		self.bar_width.store(bar_width(&self.config, &text), SeqCst);
+	}
+
+	// Lets `config.hysteresis` draw less of the bar again
+	fn reset_progress_width(&self) {
+		self.progress_width.store(if self.config.countdown { u64::MAX } else { 0 }, SeqCst);
+	}
+
+	fn update_text(&self, f: impl FnOnce(&mut Text)) {
+		let mut text = self.text.lock().unwrap();
+		f(&mut text);
+		self.bar_width.store(bar_width(&self.config, &text), SeqCst);
+	}
+
+	#[inline]
+	fn update(&self) {
+		self.update_with(|| ());
+	}
+
+	// Runs `before` right before a redraw, so that what it computes for the frame isn't computed for the frames that
+	// are throttled away. The render thread can draw at any time, so it needs it every time
+	fn update_with(&self, before: impl FnOnce()) {
+		if self.failed.load(SeqCst) {
+			return;
+		}
+
+		self.report_progress();
+
+		if self.config.render_thread {
+			return before();
+		}
+
+		if self.due() {
+			before();
+			self.print().unwrap();
+
+			if !self.config.frame_delay.is_zero() {
+				thread::sleep(self.config.frame_delay);
+			}
+		}
+	}
+
+	// Calls `on_progress` if the whole percentage has changed since the last call. Not while the length is unknown
+	fn report_progress(&self) {
+		let Some(on_progress) = &self.config.on_progress else { return };
+
+		if self.unbounded.load(SeqCst) || self.on_progress_panicked.load(SeqCst) {
+			return;
+		}
+
+		let len = self.len();
+		let pos = self.pos.get().min(len);
+		let percent = self.whole_percent(pos);
+
+		if self.last_percent.swap(percent, SeqCst) != percent && panic::catch_unwind(AssertUnwindSafe(|| on_progress(pos, len))).is_err() {
+			self.on_progress_panicked.store(true, SeqCst);
+		}
+	}
+
+	// Whether `inc` should redraw the bar, taking the redraw if so
+	fn due(&self) -> bool {
+		let elapsed = self.elapsed_nanos();
+		let pos = self.pos.get();
+		let throttle = self.throttle.load(SeqCst);
+
+		if throttle == 0 {
+			self.last_update.store(elapsed, SeqCst);
+			self.last_update_pos.store(pos, SeqCst);
+			return true;
+		}
+
+		let last_update = self.last_update.load(SeqCst);
+		let last_update_pos = self.last_update_pos.load(SeqCst);
+		let steps_due = self.config.throttle_steps.is_some_and(|steps| pos.saturating_sub(last_update_pos) >= steps);
+		let time_due = || elapsed.saturating_sub(last_update) > throttle;
+		let percent_due = || self.whole_percent(pos) != self.whole_percent(last_update_pos);
+
+		let due = steps_due || match self.config.throttle_mode {
+			ThrottleMode::Time => time_due(),
+			ThrottleMode::Percent => percent_due(),
+			ThrottleMode::TimeOrPercent => time_due() || percent_due(),
+		};
+
+		if due
+			&& elapsed.saturating_sub(last_update) >= nanos(self.config.min_redraw_interval)
+			&& self.last_update.compare_exchange(last_update, elapsed, SeqCst, SeqCst).is_ok()
+		{
+			self.last_update_pos.store(pos, SeqCst);
+			return true;
+		}
+
+		false
+	}
+
+	// Redraws from the steady tick, which only `min_redraw_interval` holds back
+	fn tick(&self) -> std::io::Result<()> {
+		// An observed counter moves without `inc`
+		self.report_progress();
+		let elapsed = self.elapsed_nanos();
+
+		if elapsed.saturating_sub(self.last_update.load(SeqCst)) < nanos(self.config.min_redraw_interval) {
+			return Ok(());
+		}
+
+		self.draw()
+	}
+
+	// Draws regardless of throttling, as a redraw that the throttling goes on from
+	fn draw(&self) -> std::io::Result<()> {
+		self.last_update.store(self.elapsed_nanos(), SeqCst);
+		self.last_update_pos.store(self.pos.get(), SeqCst);
+		self.print()
+	}
+
+	// Whether a line is due every `log_interval` since `last_log`, which is updated if it is. The first and the last one
+	// always are, so that a slow first step doesn't leave the log empty
+	fn log_due(&self, last_log: &AtomicU64, last: bool) -> bool {
+		let elapsed = self.elapsed_nanos();
+		let previous = last_log.load(SeqCst);
+		let interval = nanos(self.config.log_interval);
+		let due = previous == NEVER || elapsed.saturating_sub(previous) >= interval;
+		last || (due && last_log.compare_exchange(previous, elapsed, SeqCst, SeqCst).is_ok())
+	}
+
+	fn warming_up(&self, pos: u64, elapsed: Duration) -> bool {
+		let done = pos.saturating_sub(self.start_pos);
+		let len = self.len();
+		pos < len && elapsed < self.config.eta_warmup && u128::from(done) * 100 < u128::from(len)
+	}
+
+	fn whole_percent(&self, pos: u64) -> u64 {
+		match self.len() {
+			0 => 100,
+			len => (u128::from(pos.min(len)) * 100 / u128::from(len)) as u64,
+		}
+	}
+
+	#[inline]
+	fn len(&self) -> u64 {
+		self.len.load(SeqCst)
+	}
+
+	fn elapsed_nanos(&self) -> u64 {
+		nanos(self.clock.now().saturating_duration_since(self.start_time))
+	}
+}
+
+const NEVER: u64 = u64::MAX;
+
+// The length of a `Bar::new_fraction`, in millionths
+const FRACTION_LEN: u64 = 1_000_000;
+
+// Relative to the mean rate, past which `Config::eta_range` shows a range
+const ETA_RANGE_DEVIATION: f64 = 0.2;
+
+// A frame every 100 ms while the length is unknown
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+// Enough for 584 years
+fn nanos(duration: Duration) -> u64 {
+	duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+struct Ticker {
+	stop: Arc<(Mutex<bool>, Condvar)>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl Ticker {
+	// Calls `tick` with the state every `interval`
+	fn spawn(state: Arc<State>, interval: Duration, tick: impl Fn(&State) + Send + 'static) -> Self {
+		let stop = Arc::new((Mutex::new(false), Condvar::new()));
+
+		let thread = thread::spawn({
+			let stop = stop.clone();
+
+			move || {
+				let (stopped, condvar) = &*stop;
+				let mut stopped = stopped.lock().unwrap();
+
+				while !*stopped {
+					stopped = condvar.wait_timeout(stopped, interval).unwrap().0;
+
+					if !*stopped {
+						tick(&state);
+					}
+				}
+			}
+		});
+
+		Self { stop, thread: Some(thread) }
+	}
+}
+
+impl Drop for Ticker {
+	fn drop(&mut self) {
+		let (stopped, condvar) = &*self.stop;
+		*stopped.lock().unwrap() = true;
+		condvar.notify_one();
+
+		if let Some(thread) = self.thread.take() {
+			thread.join().ok();
+		}
+	}
+}
+
+pub struct Phases<'a> {
+	config: Config<'a>,
+	prefixes: Vec<String>,
+	next: usize,
+}
+
+impl<'a> Phases<'a> {
+	pub fn new(names: &[&str], config: Config<'a>) -> Self {
+		let name_width = names.iter().map(|name| text_width(name)).max().unwrap_or(0);
+		let prefixes = names.iter().enumerate()
+			.map(|(i, name)| format!("{}[{}/{}] {name}{:padding$} ", config.prefix, i + 1, names.len(), "", padding = name_width - text_width(name)))
+			.collect();
+		Self { config, prefixes, next: 0 }
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.prefixes.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.prefixes.is_empty()
+	}
+
+	/// Starts the bar for `phase`. The returned bar borrows `self` mutably, so the previous phase has to be finished
+	/// (or dropped) before the next one can be started.
+	pub fn start(&mut self, phase: usize, len: u64) -> Bar<'_> {
+		assert!(phase < self.prefixes.len(), "phase {} is out of range, there are only {} phases", phase + 1, self.prefixes.len());
+		assert!(phase >= self.next, "phase {} has already been started", phase + 1);
+		self.next = phase + 1;
+		Bar::new(len, Config { prefix: &self.prefixes[phase], ..self.config.clone() })
+	}
+}
+
+/// A cheaply cloneable progress count with no knowledge of rendering. This is the recommended way for libraries to
+/// report progress: accept a `ProgressCounter` and bump it, leaving it to the application to display it with
+/// `Bar::observe` (or `AggregateBar`), or not at all.
+#[derive(Clone, Default)]
+pub struct ProgressCounter(Arc<AtomicU64>);
+
+impl ProgressCounter {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Saturates at `u64::MAX` instead of wrapping around.
+	#[inline]
+	pub fn add(&self, delta: u64) {
+		self.add_and_get(delta);
+	}
+
+	// The count after this very addition, whatever other threads add
+	#[inline]
+	fn add_and_get(&self, delta: u64) -> u64 {
+		// Checking after the fact keeps the common case a single atomic add
+		self.0.fetch_add(delta, SeqCst).checked_add(delta).unwrap_or_else(|| {
+			self.0.store(u64::MAX, SeqCst);
+			u64::MAX
+		})
+	}
+
+	/// Raises the count to `value`, leaving a higher one as it is.
+	#[inline]
+	pub fn inc_to(&self, value: u64) {
+		self.0.fetch_max(value, SeqCst);
+	}
+
+	#[inline]
+	pub fn set(&self, value: u64) {
+		self.0.store(value, SeqCst);
+	}
+
+	#[inline]
+	fn replace(&self, value: u64) -> u64 {
+		self.0.swap(value, SeqCst)
+	}
+
+	#[inline]
+	pub fn get(&self) -> u64 {
+		self.0.load(SeqCst)
+	}
+}
+
+/// A progress reporter for library functions to take as `&dyn Progress`, so that the caller can pass a `Bar`,
+/// `NoProgress`, or an implementation of their own. Unlike with a `ProgressCounter`, the library sets the length and
+/// the message itself.
+pub trait Progress: Send + Sync {
+	fn inc(&self, delta: u64);
+	fn set_position(&self, pos: u64);
+	fn set_message(&self, message: &str);
+	fn set_len(&self, len: u64);
+	/// Draws the final frame. Reporting more progress afterwards does nothing.
+	fn finish(&self);
+}
+
+impl Progress for Bar<'_> {
+	#[inline]
+	fn inc(&self, delta: u64) {
+		Bar::inc(self, delta);
+	}
+
+	#[inline]
+	fn set_position(&self, pos: u64) {
+		Bar::set_position(self, pos);
+	}
+
+	#[inline]
+	fn set_message(&self, message: &str) {
+		Bar::set_message(self, message);
+	}
+
+	#[inline]
+	fn set_len(&self, len: u64) {
+		Bar::set_len(self, len);
+	}
+
+	#[inline]
+	fn finish(&self) {
+		self.state.finish().unwrap();
+	}
+}
+
+impl<T: Progress + ?Sized> Progress for Arc<T> {
+	#[inline]
+	fn inc(&self, delta: u64) {
+		(**self).inc(delta);
+	}
+
+	#[inline]
+	fn set_position(&self, pos: u64) {
+		(**self).set_position(pos);
+	}
+
+	#[inline]
+	fn set_message(&self, message: &str) {
+		(**self).set_message(message);
+	}
+
+	#[inline]
+	fn set_len(&self, len: u64) {
+		(**self).set_len(len);
+	}
+
+	#[inline]
+	fn finish(&self) {
+		(**self).finish();
+	}
+}
+
+/// A `Progress` that shows nothing, for callers that don't want a bar.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+	fn inc(&self, _delta: u64) {}
+	fn set_position(&self, _pos: u64) {}
+	fn set_message(&self, _message: &str) {}
+	fn set_len(&self, _len: u64) {}
+	fn finish(&self) {}
+}
+
+/// Renders the sum of several independent counters, clamped to `len`, and finishes as soon as the sum reaches `len`.
+/// The initial frame is drawn right away, and the later ones by `refresh` or by the steady tick of `with_steady_tick`.
+pub struct AggregateBar<'a> {
+	bar: Bar<'a>,
+	counters: Arc<[ProgressCounter]>,
+}
+
+impl<'a> AggregateBar<'a> {
+	#[inline]
+	pub fn new(counters: Vec<ProgressCounter>, len: u64, config: Config<'a>) -> Self {
+		Self { bar: Bar::new(len, config), counters: counters.into() }
+	}
+
+	/// Sums the counters and redraws the bar from a background thread every `interval`, so that nobody has to call
+	/// `refresh`.
+	pub fn with_steady_tick(counters: Vec<ProgressCounter>, len: u64, config: Config<'a>, interval: Duration) -> Self {
+		let mut aggregate = Self::new(counters, len, config);
+		let counters = aggregate.counters.clone();
+
+		aggregate.bar.ticker = Some(Ticker::spawn(aggregate.bar.state.clone(), interval, move |state| {
+			if sync(state, &counters) {
+				state.finish().ok();
+			} else {
+				state.tick().ok();
+			}
+		}));
+
+		aggregate
+	}
+
+	/// Redraws the bar (subject to throttling), or draws the final frame once the counters have reached `len`, and
+	/// returns whether they have.
+	pub fn refresh(&self) -> bool {
+		let finished = sync(&self.bar.state, &self.counters);
+
+		if finished {
+			self.bar.state.finish().unwrap();
+		} else {
+			self.bar.state.update();
+		}
+
+		finished
+	}
+
+	#[inline]
+	pub fn is_finished(&self) -> bool {
+		sum(&self.counters) >= self.bar.state.len()
+	}
+
+	#[inline]
+	pub fn finish(self) {
+		drop(self);
+	}
+}
+
+impl Drop for AggregateBar<'_> {
+	#[inline]
+	fn drop(&mut self) {
+		sync(&self.bar.state, &self.counters);
+	}
+}
+
+// Sets the position to the sum of the counters and returns whether it has reached the length
+fn sync(state: &State, counters: &[ProgressCounter]) -> bool {
+	let sum = sum(counters);
+	state.pos.set(sum.min(state.len()));
+	sum >= state.len()
+}
+
+fn sum(counters: &[ProgressCounter]) -> u64 {
+	counters.iter().fold(0, |sum, counter| sum.saturating_add(counter.get()))
+}
+
+#[cfg(feature = "terminal")]
+type Stderr = std::io::StderrLock<'static>;
+#[cfg(not(feature = "terminal"))]
+type Stderr = std::io::Sink;
+
+// All terminal output goes through here, so that without the `terminal` feature nothing is written
+#[cfg(feature = "terminal")]
+fn terminal() -> Stderr {
+	std::io::stderr().lock()
+}
+
+#[cfg(not(feature = "terminal"))]
+fn terminal() -> Stderr {
+	std::io::sink()
+}
+
+// Stderr, the device from `Config::terminal`, or `Config::writer`
+enum Terminal<'a> {
+	Stderr(Stderr),
+	File(&'a File),
+	Writer(MutexGuard<'a, dyn Write + Send + 'static>),
+}
+
+impl<'a> Terminal<'a> {
+	// A writer that panicked mid-frame is still good for the next one, which may be the last frame during a panic
+	fn writer(writer: &'a LogWriter) -> Self {
+		Self::Writer(writer.lock().unwrap_or_else(PoisonError::into_inner))
+	}
+}
+
+impl Write for Terminal<'_> {
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self {
+			Self::Stderr(stderr) => stderr.write(buf),
+			Self::File(file) => file.write(buf),
+			Self::Writer(writer) => writer.write(buf),
+		}
+	}
+
+	#[inline]
+	fn flush(&mut self) -> std::io::Result<()> {
+		match self {
+			Self::Stderr(stderr) => stderr.flush(),
+			Self::File(file) => file.flush(),
+			Self::Writer(writer) => writer.flush(),
+		}
+	}
+}
+
+// The explicit width, then the terminal's, then `COLUMNS`, which shells set even when the terminal can't be queried,
+// e.g. when stderr is piped. `None` leaves it to `default_width`
+#[allow(unused_variables)]
+fn line_width(width: Option<u64>, terminal: Option<&File>, var: impl Fn(&str) -> Option<String>) -> Option<u64> {
+	#[cfg(all(windows, feature = "windows-console"))]
+	let width = width.or_else(|| windows::terminal_width().filter(|_| terminal.is_none()));
+	#[cfg(feature = "terminal_size")]
+	let width = width.or_else(|| terminal_width(terminal));
+	// Malformed or zero, e.g. unset by a wrapper, it is no better than nothing
+	width.or_else(|| var("COLUMNS")?.trim().parse().ok().filter(|&columns| columns > 0))
+}
+
+#[cfg(feature = "terminal_size")]
+fn terminal_width(file: Option<&File>) -> Option<u64> {
+	terminal_dimensions(file).map(|(width, _)| width)
+}
+
+// Rows of the terminal, for `Config::pinned`
+#[allow(unused_variables)]
+fn terminal_height(file: Option<&File>) -> Option<u64> {
+	#[cfg(feature = "terminal_size")]
+	return terminal_dimensions(file).map(|(_, height)| height);
+	#[cfg(not(feature = "terminal_size"))]
+	None
+}
+
+#[cfg(feature = "terminal_size")]
+fn terminal_dimensions(file: Option<&File>) -> Option<(u64, u64)> {
+	let size = match file {
+		#[cfg(unix)]
+		Some(file) => terminal_size::terminal_size_using_fd(std::os::unix::io::AsRawFd::as_raw_fd(file)),
+		#[cfg(windows)]
+		Some(file) => terminal_size::terminal_size_using_handle(std::os::windows::io::AsRawHandle::as_raw_handle(file)),
+		_ => terminal_size::terminal_size(),
+	};
+
+	size.map(|(width, height)| (u64::from(width.0), u64::from(height.0)))
+}
+
+// Whether the bar is drawn to a terminal, as opposed to a pipe, a file or nowhere at all
+fn is_terminal(config: &Config) -> bool {
+	use std::io::IsTerminal;
+
+	match &config.terminal {
+		_ if config.writer.is_some() => true,
+		Some(file) => file.is_terminal(),
+		None => cfg!(feature = "terminal") && std::io::stderr().is_terminal(),
+	}
+}
+
+// Without escape sequences every frame is printed on its own line, which doesn't need clearing
+fn line_mode() -> bool {
+	#[cfg(all(windows, feature = "windows-console"))]
+	return !windows::virtual_terminal();
+	#[cfg(not(all(windows, feature = "windows-console")))]
+	false
+}
+
+// Colors are only written to a terminal that understands escapes, and never when the user opted out with NO_COLOR
+// (https://no-color.org), whatever its value
+fn use_color(config: &Config) -> bool {
+	config.sink.is_none() && config.writer.is_none() && !line_mode() && is_terminal(config) && std::env::var_os("NO_COLOR").is_none()
+}
+
+// Redrawing in place only makes sense on a terminal
+fn log_lines(config: &Config) -> bool {
+	use std::io::IsTerminal;
+
+	config.sink.is_none() && config.writer.is_none() && match &config.terminal {
+		Some(file) => !file.is_terminal(),
+		None => stderr_redirected(),
+	}
+}
+
+// Stderr isn't a terminal, which without the `terminal` feature doesn't matter as nothing is written to it
+fn stderr_redirected() -> bool {
+	use std::io::IsTerminal;
+
+	cfg!(feature = "terminal") && !std::io::stderr().is_terminal()
+}
+
+// Clears everything from `lines` lines above the cursor down
+fn clear_lines(w: &mut impl Write, lines: usize) -> std::io::Result<()> {
+	if line_mode() {
+		return Ok(());
+	}
+
+	if lines > 0 {
+		write!(w, "\x1b[{lines}A")?;
+	}
+
+	write!(w, "\r\x1b[J")
+}
+
+fn unicode_supported() -> bool {
+	if cfg!(windows) {
+		// The legacy console can't be detected, but Windows Terminal and VS Code announce themselves
+		return std::env::var_os("WT_SESSION").is_some() || std::env::var_os("TERM_PROGRAM").is_some_and(|program| program == "vscode");
+	}
+
+	// The first non-empty of these defines the character encoding
+	["LC_ALL", "LC_CTYPE", "LANG"].iter()
+		.find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+		.is_some_and(|locale| {
+			let locale = locale.to_ascii_lowercase();
+			locale.contains("utf-8") || locale.contains("utf8")
+		})
+}
+
+fn bar_width(config: &Config, text: &Text) -> u64 {
+	config.width.unwrap_or(config.default_width).saturating_sub(overhead(config, text))
+}
+
+// Columns taken by everything but the bar itself, its edge included
+fn overhead(config: &Config, text: &Text) -> u64 {
+	let Text { prefix, suffix, unit, .. } = text;
+	let overhead = text_width(prefix)
+		+ if config.show_percent { " 100%".len() } else { 0 }
+		+ if suffix.is_empty() { 0 } else { 1 + text_width(suffix) }
+		+ if config.show_elapsed { label_width(&config.labels.elapsed) + 1 + time_width(config) } else { 0 }
+		+ if config.show_count {
+			label_width(config.labels.count(config.countdown)) + text_width(&config.labels.separator) + 1 + text.num_width * 2
+				+ if unit.is_empty() { 0 } else { 1 + text_width(unit) }
+		} else {
+			0
+		}
+		+ if config.show_bar { config.bar_gap + 1 + if config.show_delimiters { 2 } else { 0 } } else { 0 }
+		+ if !config.show_bar && config.finish_icon.is_some() { 2 } else { 0 }
+		+ if config.show_eta { label_width(&config.labels.eta) + 1 + eta_width(config) } else { 0 }
+		+ match (config.show_rate, config.show_current_rate) {
+			(true, true) => {
+				label_width(&config.labels.average) + 1 + " ·".chars().count() + label_width(&config.labels.current) + 1
+					+ rate_width(config, text) * 2
+			}
+			(true, false) | (false, true) => 1 + rate_width(config, text),
+			(false, false) => 0,
+		};
+	overhead as u64
+}
+
+fn eta_width(config: &Config) -> usize {
+	if config.eta_range { time_width(config) * 2 + 1 } else { time_width(config) }
+}
+
+fn time_width(config: &Config) -> usize {
+	config.format_time.as_ref().map_or(config.time_format.width(), |(_, width)| *width)
+}
+
+// A label takes a space before it, unless it is empty
+fn label_width(label: &str) -> usize {
+	if label.is_empty() { 0 } else { 1 + text_width(label) }
+}
+
+fn push_label(line: &mut String, label: &str) {
+	if !label.is_empty() {
+		line.push(' ');
+		line.push_str(label);
+	}
+}
+
+fn truncate(text: &str, max_width: usize) -> Cow<'_, str> {
+	if text_width(text) <= max_width {
+		return Cow::Borrowed(text);
+	}
+
+	if max_width == 0 {
+		return Cow::Borrowed("");
+	}
+
+	// Room for the ellipsis
+	let mut width = 1;
+	let end = text.char_indices().find(|&(_, c)| { width += char_width(c); width > max_width }).map_or(text.len(), |(i, _)| i);
+	Cow::Owned(format!("{}…", &text[..end]))
+}
+
+// Removes CSI sequences, which is all the crate writes
+fn strip_escapes(text: &str) -> Cow<'_, str> {
+	if !text.contains('\x1b') {
+		return Cow::Borrowed(text);
+	}
+
+	let mut result = String::with_capacity(text.len());
+	let mut chars = text.chars();
+
+	while let Some(c) = chars.next() {
+		if c != '\x1b' {
+			result.push(c);
+			continue;
+		}
+
+		if chars.next() == Some('[') {
+			// Parameters and intermediates up to the final byte
+			chars.by_ref().find(|c| ('\x40'..='\x7e').contains(c));
+		}
+	}
+
+	Cow::Owned(result)
+}
+
+fn format_count(count: u64, config: &Config) -> String {
+	match config.bytes {
+		Some(units) => units.format(count),
+		None => format_number(count, config.group_separator),
+	}
+}
+
+// " · " and the widest byte count ("1023.9 KiB"), so that the bar doesn't jump as the suffix changes
+const BYTES_SEGMENT_WIDTH: u64 = 13;
+
+// The suffix follows the rate itself, so a slow transfer of a large file is still shown in KiB/s
+fn format_rate(rate: f64, config: &Config, unit: &str, width: usize) -> String {
+	if let Some(units) = config.bytes {
+		return format!("{}/s", units.format(rate as u64));
+	}
+
+	// Grouped like the counts, so that it fits into the same width
+	let tenths = (rate * 10.).round() as u64;
+	let mut number = format!("{}.{}", format_number(tenths / 10, config.group_separator), tenths % 10);
+
+	// More steps per second than there are in all, e.g. for a job done in under a second
+	if text_width(&number) > width {
+		number = scale(rate);
+	}
+
+	if unit.is_empty() { format!("{number}/s") } else { format!("{number} {unit}/s") }
+}
+
+// At most "999.9k" wide
+fn scale(rate: f64) -> String {
+	const SUFFIXES: [char; 6] = ['k', 'M', 'G', 'T', 'P', 'E'];
+	let mut rate = rate / 1000.;
+	let mut suffix = 0;
+
+	// Past 999.95, the next suffix shows it as 1.0
+	while rate >= 999.95 && suffix < SUFFIXES.len() - 1 {
+		rate /= 1000.;
+		suffix += 1;
+	}
+
+	format!("{rate:.1}{}", SUFFIXES[suffix])
+}
+
+// Room for the widest rate, so that the bar doesn't jump as it changes
+fn rate_width(config: &Config, text: &Text) -> usize {
+	match text.rate_unit() {
+		_ if config.bytes.is_some() => "1023.9 KiB/s".len(),
+		"" => rate_number_width(text) + "/s".len(),
+		unit => rate_number_width(text) + " /s".len() + text_width(unit),
+	}
+}
+
+// The counts with tenths, or a scaled rate if that is wider
+fn rate_number_width(text: &Text) -> usize {
+	(text.num_width + ".0".len()).max("999.9k".len())
+}
+
+#[cfg(feature = "num-format")]
+fn format_number(number: u64, _separator: char) -> String {
+	number.to_formatted_string(&Locale::en)
+}
+
+#[cfg(not(feature = "num-format"))]
+fn format_number(number: u64, separator: char) -> String {
+	group_digits(number, separator)
+}
+
+// Compiled either way, so that it is tested with the default features too
+#[cfg_attr(feature = "num-format", allow(dead_code))]
+fn group_digits(number: u64, separator: char) -> String {
+	let digits = number.to_string();
+	let mut result = String::with_capacity(digits.len() / 3 * 4);
+
+	for (i, digit) in digits.chars().enumerate() {
+		if i > 0 && (digits.len() - i).is_multiple_of(3) {
+			result.push(separator);
+		}
+
+		result.push(digit);
+	}
+
+	result
+}
+
+// Shown rounded down, to whole seconds or, if `precise`, to tenths under 10 seconds
+#[derive(Clone, Copy)]
+struct Time {
+	millis: u64,
+	format: TimeFormat,
+	precise: bool,
+}
+
+impl Time {
+	const PRECISE_BELOW: u64 = 10_000;
+
+	// Rounded up instead, so that the ETA doesn't reach zero before the end
+	fn ceil(self) -> Self {
+		let unit = if self.precise && self.millis <= Self::PRECISE_BELOW - 100 { 100 } else { 1000 };
+		Self { millis: self.millis.div_ceil(unit).saturating_mul(unit), ..self }
+	}
+}
+
+impl Display for Time {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let Self { millis, format, precise } = *self;
+		let time = millis / 1000;
+		let (days, hours, mins, secs) = (time / 86400, time / 3600 % 24, time / 60 % 60, time % 60);
+
+		match format {
+			_ if precise && millis < Self::PRECISE_BELOW => {
+				write!(f, "{:>width$}", format!("{secs}.{}s", millis / 100 % 10), width = format.width())
+			}
+			TimeFormat::Clock if time / 3600 > 99 => write!(f, "??:??:??"),
+			TimeFormat::Clock => write!(f, "{:02}:{mins:02}:{secs:02}", time / 3600),
+			TimeFormat::Human => {
+				let human = match time {
+					_ if days > 99 => "??d".to_owned(),
+					0..60 => format!("{secs}s"),
+					60..3600 => format!("{mins}m {secs:02}s"),
+					3600..86400 => format!("{hours}h {mins:02}m"),
+					_ => format!("{days}d {hours}h"),
+				};
+
+				write!(f, "{human:>width$}", width = format.width())
+			}
 		}
 	}
 }
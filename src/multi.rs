@@ -0,0 +1,340 @@
+use std::{io::Write, fmt::{Display, Write as _}, sync::{Arc, Mutex}};
+
+use crate::{Bar, Config, Labels, LogWriter, ProgressCounter, Terminal, clear_lines, clock::SystemClock, line_mode, push_label, stderr_redirected, terminal};
+
+/// A block of bars drawn on consecutive lines. Rows of finished bars are reused by the bars added after them, and
+/// active bars that don't fit into `max_rows` are summarized by a "+N more" line.
+///
+/// Bars can be added and finished from any thread. Only drawing the block takes the shared lock, so `inc` calls
+/// between redraws don't contend with each other.
+///
+/// When stderr isn't a terminal, e.g. when it is redirected to a file, the block isn't drawn, and the bars log their
+/// frames as plain lines every `Config::log_interval`, like a single bar.
+pub struct MultiBar {
+	shared: Arc<Shared>,
+}
+
+pub(crate) struct Row {
+	shared: Arc<Shared>,
+	id: u64,
+}
+
+struct Shared {
+	rows: Mutex<Rows>,
+	max_rows: usize,
+	writer: Option<LogWriter>,
+	// Off a terminal the block isn't drawn, and the bars log their frames instead
+	plain: bool,
+}
+
+/// Where `MultiBar::show_total` puts the total.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TotalRow {
+	Top,
+	#[default]
+	Bottom,
+}
+
+/// What happens to the row of a finished bar.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FinishedRow {
+	/// The final frame stays until another bar takes the row.
+	#[default]
+	Keep,
+	/// The row is blanked, leaving a gap until another bar takes it.
+	Clear,
+	/// The row is removed and the rows below it move up.
+	Remove,
+}
+
+#[derive(Default)]
+struct Rows {
+	lines: Vec<Line>,
+	finished: FinishedRow,
+	next_id: u64,
+	// Number of lines occupied by the block on screen; the cursor stays right below it
+	drawn: usize,
+	total: Option<(Bar<'static>, TotalRow)>,
+	// Position and length of the bars whose lines are gone, for the total
+	gone: (u64, u64),
+	labels: Labels,
+}
+
+struct Line {
+	id: u64,
+	text: String,
+	active: bool,
+	// Position and length of the bar as of its last frame
+	progress: (u64, u64),
+}
+
+impl MultiBar {
+	/// Limits the block to the terminal height, if it is known.
+	#[inline]
+	pub fn new() -> Self {
+		#[cfg(feature = "terminal_size")]
+		if let Some((_, terminal_size::Height(height))) = terminal_size::terminal_size() {
+			// Leave room for the "+N more" line and the cursor
+			return Self::with_max_rows(usize::from(height).saturating_sub(2).max(1));
+		}
+
+		Self::with_max_rows(usize::MAX)
+	}
+
+	#[inline]
+	pub fn with_max_rows(max_rows: usize) -> Self {
+		Self { shared: Arc::new(Shared { rows: Mutex::default(), max_rows: max_rows.max(1), writer: None, plain: stderr_redirected() }) }
+	}
+
+	/// Draws the block to `writer` instead of stderr, like `Config::writer` does for a single bar.
+	#[inline]
+	pub fn with_writer(max_rows: usize, writer: LogWriter) -> Self {
+		Self { shared: Arc::new(Shared { rows: Mutex::default(), max_rows: max_rows.max(1), writer: Some(writer), plain: false }) }
+	}
+
+	/// Adds a bar in the first row left by a finished bar, or in a new row below the others.
+	pub fn add<'a>(&self, len: u64, config: Config<'a>) -> Bar<'a> {
+		let row = Row { shared: self.shared.clone(), id: self.shared.claim() };
+		Bar::with_counter(ProgressCounter::new(), Some(len), config, Some(row), Arc::new(SystemClock))
+	}
+
+	/// Removes the row of a bar from the block without drawing its final frame, moving the rows below it up. See also
+	/// `Bar::remove_row`.
+	pub fn remove(&self, bar: Bar) {
+		if let Some(row) = &bar.state.row {
+			assert!(Arc::ptr_eq(&row.shared, &self.shared), "the bar belongs to another MultiBar");
+			bar.state.clear().unwrap();
+		}
+	}
+
+	/// Prints a line above the block, to the writer of `with_writer` if there is one. Long lines may wrap, as the block
+	/// is redrawn from scratch below them.
+	pub fn println(&self, message: impl Display) {
+		self.suspend(|| writeln!(self.shared.terminal(), "{message}").unwrap());
+	}
+
+	/// Clears the block, runs `f`, which can write to stderr freely, and redraws the block below its output. `f` must
+	/// not update the bars of this block.
+	#[inline]
+	pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+		self.shared.suspend(f)
+	}
+
+	#[inline]
+	pub fn set_finished_rows(&self, finished: FinishedRow) {
+		self.shared.rows.lock().unwrap().finished = finished;
+	}
+
+	/// Sets the labels of the block itself, i.e. `Labels::more`. The bars have their own in `Config::labels`. Panics if
+	/// a label contains a control character, like `Bar::new` does.
+	pub fn set_labels(&self, labels: Labels) {
+		if let Err(error) = labels.check() {
+			panic!("{error}");
+		}
+
+		self.shared.rows.lock().unwrap().labels = labels;
+	}
+
+	/// Adds a row with a bar of the total progress of the block, i.e. the positions of its bars over their lengths,
+	/// finished bars included. The row stays at the top or the bottom of the block, on top of `max_rows`.
+	pub fn show_total(&self, row: TotalRow, config: Config<'static>) {
+		// Rendered by the block, never drawn by itself
+		let config = Config { sink: Some(Arc::new(|_| {})), ..config };
+		let mut rows = self.shared.rows.lock().unwrap();
+		rows.total = Some((Bar::new(0, config), row));
+		self.shared.draw(&mut rows).unwrap();
+	}
+}
+
+impl Default for MultiBar {
+	#[inline]
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Row {
+	pub(crate) fn update(&self, text: String, progress: (u64, u64)) -> std::io::Result<()> {
+		let mut rows = self.shared.rows.lock().unwrap();
+
+		// The block can't be redrawn in place, so just print the frames as they come
+		if line_mode() {
+			return writeln!(self.shared.terminal(), "{text}");
+		}
+
+		if let Some(line) = rows.lines.iter_mut().find(|line| line.id == self.id) {
+			line.text = text;
+			line.progress = progress;
+		}
+
+		self.shared.draw(&mut rows)
+	}
+
+	pub(crate) fn finish(&self, text: String, progress: (u64, u64)) -> std::io::Result<()> {
+		let mut rows = self.shared.rows.lock().unwrap();
+
+		if line_mode() {
+			writeln!(self.shared.terminal(), "{text}")?;
+		}
+		let max_rows = self.shared.max_rows;
+
+		if let Some(i) = rows.lines.iter().position(|line| line.id == self.id) {
+			if rows.finished == FinishedRow::Remove {
+				rows.lines.remove(i);
+				rows.add_gone(progress);
+			} else {
+				rows.lines[i].text = if rows.finished == FinishedRow::Keep { text } else { String::new() };
+				rows.lines[i].active = false;
+				rows.lines[i].progress = progress;
+
+				// Bring a hidden active bar into view in place of the finished one
+				if i < max_rows {
+					if let Some(hidden) = rows.lines.iter().skip(max_rows).position(|line| line.active) {
+						rows.lines[i] = rows.lines.remove(max_rows + hidden);
+					}
+				}
+			}
+		}
+
+		while rows.lines.len() > max_rows && rows.lines.last().is_some_and(|line| !line.active) {
+			let line = rows.lines.pop().unwrap();
+			rows.add_gone(line.progress);
+		}
+
+		self.shared.draw(&mut rows)
+	}
+
+	#[inline]
+	pub(crate) fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+		self.shared.suspend(f)
+	}
+
+	#[inline]
+	pub(crate) fn plain(&self) -> bool {
+		self.shared.plain
+	}
+
+	pub(crate) fn log(&self, text: &str) -> std::io::Result<()> {
+		let _rows = self.shared.rows.lock().unwrap();
+		writeln!(self.shared.terminal(), "{text}")
+	}
+
+	// The progress of the bar stays in the total
+	pub(crate) fn remove(&self, progress: (u64, u64)) -> std::io::Result<()> {
+		let mut rows = self.shared.rows.lock().unwrap();
+		let count = rows.lines.len();
+		rows.lines.retain(|line| line.id != self.id);
+
+		if rows.lines.len() < count {
+			rows.add_gone(progress);
+		}
+
+		self.shared.draw(&mut rows)
+	}
+}
+
+impl Shared {
+	fn claim(&self) -> u64 {
+		let mut rows = self.rows.lock().unwrap();
+		let id = rows.next_id;
+		rows.next_id += 1;
+		let line = Line { id, text: String::new(), active: true, progress: (0, 0) };
+
+		match rows.lines.iter().position(|line| !line.active) {
+			Some(i) => {
+				let finished = std::mem::replace(&mut rows.lines[i], line);
+				rows.add_gone(finished.progress);
+			}
+			None => rows.lines.push(line),
+		}
+
+		id
+	}
+
+	fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+		let mut rows = self.rows.lock().unwrap();
+
+		if !self.plain {
+			clear_lines(&mut self.terminal(), rows.drawn).unwrap();
+		}
+
+		rows.drawn = 0;
+		let result = f();
+		self.draw(&mut rows).unwrap();
+		result
+	}
+
+	fn draw(&self, rows: &mut Rows) -> std::io::Result<()> {
+		if line_mode() || self.plain {
+			return Ok(());
+		}
+
+		let mut out = String::new();
+
+		if rows.drawn > 0 {
+			write!(out, "\x1b[{}A", rows.drawn).unwrap();
+		}
+
+		let visible = rows.lines.len().min(self.max_rows);
+		let total = rows.total();
+		let mut drawn = visible + usize::from(total.is_some());
+
+		if let Some((text, TotalRow::Top)) = &total {
+			write!(out, "\r\x1b[2K{text}\n").unwrap();
+		}
+
+		for line in &rows.lines[..visible] {
+			write!(out, "\r\x1b[2K{}\n", line.text).unwrap();
+		}
+
+		let hidden = rows.lines[visible..].iter().filter(|line| line.active).count();
+
+		if hidden > 0 {
+			write!(out, "\r\x1b[2K+{hidden}").unwrap();
+			push_label(&mut out, &rows.labels.more);
+			out.push('\n');
+			drawn += 1;
+		}
+
+		if let Some((text, TotalRow::Bottom)) = &total {
+			write!(out, "\r\x1b[2K{text}\n").unwrap();
+		}
+
+		// Clear what is left of a taller block
+		if rows.drawn > drawn {
+			out.extend(std::iter::repeat_n("\r\x1b[2K\n", rows.drawn - drawn));
+			write!(out, "\x1b[{}A", rows.drawn - drawn).unwrap();
+		}
+
+		rows.drawn = drawn;
+		let mut terminal = self.terminal();
+		terminal.write_all(out.as_bytes())?;
+		terminal.flush()
+	}
+
+	fn terminal(&self) -> Terminal<'_> {
+		self.writer.as_ref().map_or_else(|| Terminal::Stderr(terminal()), Terminal::writer)
+	}
+}
+
+impl Rows {
+	fn add_gone(&mut self, (pos, len): (u64, u64)) {
+		self.gone = (self.gone.0.saturating_add(pos), self.gone.1.saturating_add(len));
+	}
+
+	// The line of the total bar, brought up to date with the bars of the block. The bar only gets the counts, so that
+	// the line is rendered (and `on_update` called) once per frame of the block
+	fn total(&self) -> Option<(String, TotalRow)> {
+		let (bar, row) = self.total.as_ref()?;
+		let (pos, len) = self.lines.iter().fold(self.gone, |(pos, len), line| {
+			(pos.saturating_add(line.progress.0), len.saturating_add(line.progress.1))
+		});
+
+		if bar.state.len() != len {
+			bar.state.store_len(Some(len));
+		}
+
+		bar.state.pos.set(pos);
+		Some((bar.state.frame(), *row))
+	}
+}
@@ -0,0 +1,888 @@
+use std::{borrow::Cow, fs::File, thread, panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::{AggregateBar, Bar, Clock, Config, ConfigError, FormatTime, Labels, MultiBar, PanicBehavior, Phases, Progress, ProgressCallback, ProgressCounter, ProgressSnapshot};
+use crate::{Rounding, Style, Time, TimeFormat, TotalRow, UpdateCallback, bar_with_config, bar_with_label, format_number, group_digits, line_width, scale, strip_escapes};
+use crate::{capture::FrameCapture, width::text_width, windows::enable_virtual_terminal};
+
+// Time that only moves when told to
+struct ManualClock {
+	start: Instant,
+	elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+	fn new() -> Arc<Self> {
+		Arc::new(Self { start: Instant::now(), elapsed: Mutex::new(Duration::ZERO) })
+	}
+
+	fn advance(&self, by: Duration) {
+		*self.elapsed.lock().unwrap() += by;
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Instant {
+		self.start + *self.elapsed.lock().unwrap()
+	}
+}
+
+// A bar of `len` drawing every frame to the returned capture, 60 columns wide unless set otherwise
+fn captured(len: u64, config: Config<'static>) -> (Bar<'static>, Arc<ManualClock>, FrameCapture) {
+	let (clock, capture) = (ManualClock::new(), FrameCapture::new());
+	let config = Config { width: config.width.or(Some(60)), sink: Some(capture.sink()), ..config };
+	(Bar::with_clock(len, config, clock.clone()), clock, capture)
+}
+
+#[test]
+fn presets_at_fixed_positions() {
+	for (config, expected) in [
+		(Config::default(), [
+			" 00:00:00   0 / 100 [#                   ]   0% ETA --:--:--",
+			" 00:00:10  50 / 100 [###########         ]  50% ETA 00:00:10",
+			" 00:00:20 100 / 100 [####################] 100% ETA 00:00:00",
+			" 00:00:20 100 / 100 [####################] 100% ETA 00:00:00",
+		]),
+		(Config::cargo(), [
+			" 00:00:00   0 / 100 [>                   ]   0% ETA --:--:--",
+			" 00:00:10  50 / 100 [==========>         ]  50% ETA 00:00:10",
+			" 00:00:20 100 / 100 [====================] 100% ETA 00:00:00",
+			" 00:00:20 100 / 100 [====================] 100% ETA 00:00:00",
+		]),
+		// The final frame swaps the edge for the icon
+		(Config::unicode(), [
+			" 00:00:00   0 / 100 [█                   ]   0% ETA --:--:--",
+			" 00:00:10  50 / 100 [███████████         ]  50% ETA 00:00:10",
+			" 00:00:20 100 / 100 [████████████████████] 100% ETA 00:00:00",
+			" 00:00:20 100 / 100 [███████████████████✓] 100% ETA 00:00:00",
+		]),
+	] {
+		let (bar, clock, capture) = captured(100, config);
+		clock.advance(Duration::from_secs(10));
+		bar.inc(50);
+		clock.advance(Duration::from_secs(10));
+		bar.inc(50);
+		drop(bar);
+		assert_eq!(capture.frames(), expected);
+	}
+}
+
+#[test]
+fn writer_gets_the_frames_redrawn_in_place() {
+	let (clock, capture, output) = (ManualClock::new(), FrameCapture::new(), Arc::new(Mutex::new(Vec::new())));
+	let (bar, _, sunk) = captured(2, Config::default());
+	let bar_to_writer = Bar::with_clock(2, Config { width: Some(60), writer: Some(capture.writer()), ..Default::default() }, clock.clone());
+	let raw = Bar::with_clock(2, Config { width: Some(60), writer: Some(output.clone()), ..Default::default() }, clock);
+	drop((bar, bar_to_writer, raw));
+
+	assert_eq!(capture.frames(), sunk.frames());
+	assert_eq!(capture.last_frame(), sunk.frames().pop());
+	let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+	assert_eq!(output, format!("\r{}\r\r{}\n", sunk.frames()[0], sunk.frames()[1]));
+}
+
+#[test]
+fn throttle_interval() {
+	let (bar, clock, capture) = captured(100, Config { throttle: Duration::from_millis(100), ..Default::default() });
+	bar.inc(1);
+	clock.advance(Duration::from_millis(100));
+	bar.inc(1);
+	assert_eq!(capture.frames().len(), 1);
+
+	// Strictly more than the throttle since the last frame
+	clock.advance(Duration::from_millis(1));
+	bar.inc(1);
+	bar.inc(1);
+	assert_eq!(capture.frames().len(), 2);
+	assert!(capture.last_frame().unwrap().contains("  3 / 100 "));
+}
+
+#[test]
+fn eta_from_the_average_rate() {
+	let (bar, clock, capture) = captured(100, Config::default());
+	clock.advance(Duration::from_secs(10));
+	bar.inc(25);
+	assert_eq!(bar.snapshot().eta_ms, Some(30_000));
+	assert!(capture.last_frame().unwrap().ends_with(" 25% ETA 00:00:30"));
+
+	// Rounded up, so that it doesn't reach zero before the end
+	clock.advance(Duration::from_secs(20));
+	bar.inc(74);
+	assert_eq!(bar.snapshot().eta_ms, Some(304));
+	assert!(capture.last_frame().unwrap().ends_with(" 99% ETA 00:00:01"));
+}
+
+#[test]
+fn clock_rolls_over_past_99_hours() {
+	let (bar, clock, capture) = captured(100, Config::default());
+	clock.advance(Duration::from_secs(100 * 3600 - 1));
+	bar.inc(1);
+	assert!(capture.last_frame().unwrap().starts_with(" 99:59:59 "));
+
+	clock.advance(Duration::from_secs(1));
+	bar.inc(1);
+	assert!(capture.last_frame().unwrap().starts_with(" ??:??:?? "));
+	// The ETA of 49 times that
+	assert!(capture.last_frame().unwrap().ends_with(" ETA ??:??:??"));
+}
+
+// The raw output of a `MultiBar` to a buffer
+fn block(max_rows: usize) -> (MultiBar, Arc<Mutex<Vec<u8>>>) {
+	let output = Arc::new(Mutex::new(Vec::new()));
+	(MultiBar::with_writer(max_rows, output.clone()), output)
+}
+
+fn text(output: &Mutex<Vec<u8>>) -> String {
+	String::from_utf8(output.lock().unwrap().clone()).unwrap()
+}
+
+#[test]
+fn multi_bar_remove_skips_the_final_frame() {
+	let (multi, output) = block(10);
+	let log = FrameCapture::new();
+	let bar = multi.add(10, Config { width: Some(40), throttle: Duration::ZERO, bell: true, log: Some(log.writer()), ..Default::default() });
+	bar.inc(5);
+	multi.remove(bar);
+
+	let output = text(&output);
+	assert!(!output.contains('\x07'));
+	assert!(output.ends_with("\x1b[1A\r\x1b[2K\n\x1b[1A"));
+	// Just the first frame, which is always logged
+	assert_eq!(log.frames().len(), 1);
+}
+
+#[test]
+fn multi_bar_println_goes_to_the_writer() {
+	let (multi, output) = block(10);
+	let _bar = multi.add(10, Config { width: Some(40), ..Default::default() });
+	multi.println("done with the first part");
+	assert!(text(&output).contains("done with the first part\n"));
+}
+
+#[test]
+fn multi_bar_total_updates_once_per_frame() {
+	let (multi, output) = block(10);
+	let (on_update, updates) = updates();
+	multi.show_total(TotalRow::Bottom, Config { width: Some(40), on_update: Some(on_update), ..Default::default() });
+	updates.lock().unwrap().clear();
+
+	// A new bar changes the length of the total
+	let bar = multi.add(10, Config { width: Some(40), throttle: Duration::ZERO, ..Default::default() });
+	assert_eq!(updates.lock().unwrap().len(), 1);
+	bar.inc(5);
+	assert_eq!(updates.lock().unwrap().len(), 2);
+	assert!(text(&output).contains(" 5 / 10 "));
+}
+
+#[test]
+fn multi_bar_stress() {
+	let (multi, output) = block(8);
+	let config = || Config { prefix: "task", width: Some(60), throttle: Duration::ZERO, ..Default::default() };
+
+	thread::scope(|scope| {
+		for worker in 0..4 {
+			let multi = &multi;
+
+			scope.spawn(move || {
+				for i in 0..25 {
+					let bar = multi.add(10, config());
+					bar.inc(3);
+
+					match (worker + i) % 3 {
+						0 => multi.remove(bar),
+						1 => bar.remove_row(),
+						_ => bar.inc(7),
+					}
+				}
+			});
+		}
+	});
+
+	// Every line is a whole frame of one of the bars, the "+N more" line, or a cleared one
+	let output = strip_escapes(&text(&output)).into_owned();
+
+	let lines: Vec<_> = output.split(['\r', '\n']).filter(|line| !line.is_empty()).collect();
+	assert!(lines.len() > 100);
+
+	for line in lines {
+		let more = line.strip_prefix('+').and_then(|line| line.strip_suffix(" more")).is_some_and(|n| n.parse::<usize>().is_ok());
+		assert!(more || (line.starts_with("task ") && text_width(line) == 60), "corrupt line: {line:?}");
+	}
+}
+
+// The `finished` flags `on_update` gets
+fn updates() -> (UpdateCallback, Arc<Mutex<Vec<bool>>>) {
+	let finished = Arc::new(Mutex::new(Vec::new()));
+	let callback = finished.clone();
+	(Arc::new(move |snapshot: &ProgressSnapshot| callback.lock().unwrap().push(snapshot.finished)), finished)
+}
+
+#[test]
+fn cleared_bars_report_finishing() {
+	let (on_update, finished) = updates();
+	let (bar, _, _) = captured(10, Config { on_update: Some(on_update.clone()), ..Default::default() });
+	bar.finish_and_clear();
+	assert_eq!(*finished.lock().unwrap(), [false, true]);
+
+	let (multi, _) = block(10);
+	finished.lock().unwrap().clear();
+	let bar = multi.add(10, Config { on_update: Some(on_update.clone()), ..Default::default() });
+	bar.remove_row();
+	drop(bar);
+	assert_eq!(*finished.lock().unwrap(), [false, true]);
+
+	finished.lock().unwrap().clear();
+	multi.remove(multi.add(10, Config { on_update: Some(on_update), ..Default::default() }));
+	assert_eq!(*finished.lock().unwrap(), [false, true]);
+}
+
+// The output of a bar dropped by a panic after a step, and what `on_update` got
+fn panicking(on_panic: PanicBehavior) -> (String, Vec<bool>) {
+	let (on_update, finished) = updates();
+	let output = Arc::new(Mutex::new(Vec::new()));
+	let config = Config { width: Some(60), writer: Some(output.clone()), on_update: Some(on_update), on_panic, ..Default::default() };
+
+	panic::catch_unwind(AssertUnwindSafe(|| {
+		let clock = ManualClock::new();
+		let bar = Bar::with_clock(10, config, clock.clone());
+		clock.advance(Duration::from_secs(1));
+		bar.inc(5);
+		panic!("step failed");
+	})).unwrap_err();
+
+	let finished = finished.lock().unwrap().clone();
+	(text(&output), finished)
+}
+
+// The first frame and the one of the step, redrawn in place
+const BEFORE_PANIC: &str = concat!(
+	"\r 00:00:00  0 / 10 [#                     ]   0% ETA --:--:--\r",
+	"\r 00:00:01  5 / 10 [############          ]  50% ETA 00:00:01\r",
+);
+
+#[test]
+fn panic_newline() {
+	let (output, finished) = panicking(PanicBehavior::Newline);
+	// The panic message starts on a line of its own
+	assert_eq!(output, format!("{BEFORE_PANIC}\n"));
+	assert_eq!(finished, [false, false, true]);
+}
+
+#[test]
+fn panic_full_frame() {
+	let (output, finished) = panicking(PanicBehavior::FullFrame);
+	assert_eq!(output, format!("{BEFORE_PANIC}\r 00:00:01  5 / 10 [############          ]  50% ETA 00:00:01\n"));
+	assert_eq!(finished, [false, false, true]);
+}
+
+#[test]
+fn panic_nothing() {
+	let (output, finished) = panicking(PanicBehavior::Nothing);
+	assert_eq!(output, BEFORE_PANIC);
+	assert_eq!(finished, [false, false, true]);
+}
+
+fn sunk(capture: &FrameCapture) -> Config<'static> {
+	Config { width: Some(60), throttle: Duration::ZERO, sink: Some(capture.sink()), ..Default::default() }
+}
+
+fn failed(frame: Option<String>) -> bool {
+	frame.unwrap().contains(" !! ")
+}
+
+#[test]
+fn bar_iter_break() {
+	let capture = FrameCapture::new();
+
+	for i in bar_with_config(0..10, sunk(&capture)) {
+		if i == 2 {
+			break;
+		}
+	}
+
+	let frame = capture.last_frame();
+	assert!(frame.as_deref().unwrap().contains("  3 / 10 "));
+	assert!(failed(frame));
+}
+
+#[test]
+fn bar_iter_question_mark() {
+	fn process(capture: &FrameCapture) -> Result<(), i32> {
+		for i in bar_with_config(0..10, sunk(capture)) {
+			if i == 2 { Err(i)? }
+		}
+
+		Ok(())
+	}
+
+	let capture = FrameCapture::new();
+	assert_eq!(process(&capture), Err(2));
+	assert!(failed(capture.last_frame()));
+}
+
+#[test]
+fn bar_iter_panic() {
+	for (on_panic, failed_frame) in [(PanicBehavior::Newline, false), (PanicBehavior::FullFrame, true)] {
+		let capture = FrameCapture::new();
+
+		panic::catch_unwind(AssertUnwindSafe(|| {
+			for i in bar_with_config(0..10, Config { on_panic, ..sunk(&capture) }) {
+				assert_ne!(i, 2);
+			}
+		})).unwrap_err();
+
+		assert_eq!(failed(capture.last_frame()), failed_frame);
+	}
+}
+
+#[test]
+fn bar_iter_exhausted() {
+	let capture = FrameCapture::new();
+	bar_with_config(0..10, sunk(&capture)).for_each(drop);
+	assert!(!failed(capture.last_frame()));
+
+	// `take` stops right after the last item of the bar without asking for the end
+	bar_with_config(0..3, sunk(&capture)).take(3).for_each(drop);
+	assert!(!failed(capture.last_frame()));
+	bar_with_config((0..10).take(3), sunk(&capture)).for_each(drop);
+	assert!(!failed(capture.last_frame()));
+
+	// But stopping short of it is like a `break`
+	bar_with_config(0..10, sunk(&capture)).take(3).for_each(drop);
+	assert!(failed(capture.last_frame()));
+}
+
+#[test]
+fn rates_fit_their_room() {
+	for config in [
+		Config { show_rate: true, ..Default::default() },
+		Config { show_current_rate: true, ..Default::default() },
+		Config { show_rate: true, show_current_rate: true, unit: "rows", ..Default::default() },
+	] {
+		// Far more per second than there are rows, once the 1% of the warmup is done
+		let (bar, clock, capture) = captured(100_000, Config { width: Some(100), throttle: Duration::ZERO, ..config });
+		clock.advance(Duration::from_millis(10));
+		bar.inc(1_000);
+		assert!(capture.last_frame().unwrap().contains("100,000.0"));
+		clock.advance(Duration::from_millis(10));
+		bar.inc(99_000);
+		drop(bar);
+
+		for frame in capture.frames() {
+			assert_eq!(text_width(&frame), 100, "{frame:?}");
+		}
+
+		assert!(capture.last_frame().unwrap().contains("5.0M"));
+	}
+}
+
+#[test]
+fn rate_scaling() {
+	assert_eq!(scale(1234.), "1.2k");
+	assert_eq!(scale(999_949.), "999.9k");
+	assert_eq!(scale(999_950.), "1.0M");
+	assert_eq!(scale(73_877_068.6), "73.9M");
+	assert_eq!(scale(u64::MAX as f64), "18.4E");
+}
+
+#[test]
+fn render_fits_the_width() {
+	let (bar, _, _) = captured(100, Config::default());
+	bar.inc(50);
+
+	// Cut short below the 41 columns of the layout with just the edge of the bar
+	for width in [0, 1, 10, 40, 41, 60, 200] {
+		assert_eq!(text_width(&bar.render(width)), usize::from(width));
+	}
+
+	assert_eq!(bar.render(10), " 00:00:00…");
+}
+
+#[test]
+fn render_leaves_the_bar_alone() {
+	let run = |render: bool| {
+		let config = Config { hysteresis: true, show_current_rate: true, throttle: Duration::ZERO, ..Default::default() };
+		let (bar, clock, capture) = captured(100, config);
+
+		for delta in [50, 1, 20] {
+			clock.advance(Duration::from_secs(1));
+
+			if render {
+				bar.render(200);
+			}
+
+			clock.advance(Duration::from_secs(1));
+			bar.inc(delta);
+		}
+
+		capture.frames()
+	};
+
+	// Neither the hysteresis nor the rates of the live frames see the renders at another width
+	assert_eq!(run(true), run(false));
+	assert!(run(true)[2].contains(" [######     ]  51% "));
+}
+
+#[test]
+fn snapshot_displays_the_line() {
+	let (bar, clock, capture) = captured(100, Config::default());
+	clock.advance(Duration::from_secs(10));
+	bar.inc(50);
+	let snapshot = bar.snapshot();
+	bar.inc(50);
+	drop(bar);
+
+	assert_eq!(snapshot.pos, 50);
+	assert_eq!(snapshot.to_string(), capture.frames()[1]);
+}
+
+#[test]
+fn hysteresis_starts_over_when_going_back() {
+	for countdown in [false, true] {
+		let (bar, _, capture) = captured(100, Config { hysteresis: true, countdown, throttle: Duration::ZERO, ..Default::default() });
+		bar.inc(90);
+		bar.set_position(0);
+		let frame = capture.last_frame().unwrap();
+		assert!(frame.contains(if countdown { "[####################] 100%" } else { "[#                   ]   0%" }), "{frame}");
+	}
+}
+
+#[test]
+fn progress_trait_objects_cross_threads() {
+	let (bar, _, capture) = captured(10, Config { throttle: Duration::ZERO, ..Default::default() });
+	let progress: Box<dyn Progress> = Box::new(bar);
+	thread::spawn(move || progress.inc(3)).join().unwrap();
+	assert!(capture.last_frame().unwrap().contains(" 3 / 10 "));
+}
+
+#[test]
+fn aggregate_bar_finishes_at_len() {
+	let (counters, capture, (on_update, finished)) = ([ProgressCounter::new(), ProgressCounter::new()], FrameCapture::new(), updates());
+	let aggregate = AggregateBar::new(counters.to_vec(), 10, Config { on_update: Some(on_update), ..sunk(&capture) });
+	counters[0].add(4);
+	assert!(!aggregate.refresh());
+	// Over-counting is clamped
+	counters[1].add(9);
+	assert!(aggregate.refresh());
+	assert_eq!(finished.lock().unwrap().last(), Some(&true));
+	assert!(capture.last_frame().unwrap().contains(" 10 / 10 "));
+
+	// Nothing is drawn after the final frame
+	let frames = capture.frames().len();
+	drop(aggregate);
+	assert_eq!(capture.frames().len(), frames);
+}
+
+#[test]
+fn aggregate_bar_steady_tick() {
+	let (counter, capture, (on_update, finished)) = (ProgressCounter::new(), FrameCapture::new(), updates());
+	let config = Config { on_update: Some(on_update), ..sunk(&capture) };
+	let aggregate = AggregateBar::with_steady_tick(vec![counter.clone()], 10, config, Duration::from_millis(1));
+	counter.add(10);
+	let deadline = Instant::now() + Duration::from_secs(10);
+
+	while finished.lock().unwrap().last() != Some(&true) {
+		assert!(Instant::now() < deadline, "the tick never finished the bar");
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	assert!(aggregate.is_finished());
+	assert!(capture.last_frame().unwrap().contains(" 10 / 10 "));
+}
+
+#[test]
+fn panicking_time_formatter() {
+	let format_time: FormatTime = Arc::new(|_, _| panic!("formatter"));
+	let config = Config { format_time: Some((format_time, 10)), on_panic: PanicBehavior::FullFrame, throttle: Duration::ZERO, ..Default::default() };
+	let (bar, _, capture) = captured(10, config);
+
+	// The text lock isn't poisoned, so even the final frame of a panic is drawn
+	panic::catch_unwind(AssertUnwindSafe(|| {
+		bar.inc(5);
+		let _bar = bar;
+		panic!("work");
+	})).unwrap_err();
+
+	let frames = capture.frames();
+	assert_eq!(frames.len(), 3);
+	assert!(frames.iter().all(|frame| frame.starts_with("   00:00:00 ")), "{frames:?}");
+}
+
+#[test]
+fn labelled_iteration_records_steps() {
+	let capture = FrameCapture::new();
+	let mut bars = bar_with_label(0..3, Config { step_stats: true, ..sunk(&capture) }, |i| i.to_string());
+	bars.by_ref().for_each(drop);
+	let stats = bars.bar().stats();
+	assert_eq!(stats.steps, 3);
+	assert!(stats.min_step.is_some() && stats.max_step.is_some());
+}
+
+fn config_error(config: Config) -> ConfigError {
+	Bar::try_new(100, config).err().unwrap()
+}
+
+#[test]
+fn config_error_prefix_too_long() {
+	let error = config_error(Config { prefix: "twenty columns long ", width: Some(10), ..Default::default() });
+	assert_eq!(error, ConfigError::PrefixTooLong { width: 10, prefix_width: 20 });
+}
+
+#[test]
+fn config_error_too_narrow() {
+	assert_eq!(config_error(Config { width: Some(30), ..Default::default() }), ConfigError::TooNarrow { width: 30, overhead: 41 });
+}
+
+#[test]
+fn config_error_empty_bar() {
+	assert_eq!(config_error(Config { width: Some(41), min_bar_width: 0, ..Default::default() }), ConfigError::EmptyBar { width: 41 });
+	assert!(Bar::try_new(100, Config { width: Some(41), show_bar: false, sink: Some(Arc::new(|_| {})), ..Default::default() }).is_ok());
+}
+
+#[test]
+fn config_error_bar_too_short() {
+	let error = config_error(Config { width: Some(45), min_bar_width: 10, ..Default::default() });
+	assert_eq!(error, ConfigError::BarTooShort { bar_width: 5, min_bar_width: 10 });
+}
+
+#[test]
+fn config_error_control_character() {
+	assert_eq!(config_error(Config { prefix: "a\tb", ..Default::default() }), ConfigError::ControlCharacter { field: "prefix" });
+	assert_eq!(config_error(Config { space_char: '\n', ..Default::default() }), ConfigError::ControlCharacter { field: "space_char" });
+	// Nor in the labels
+	let labels = Labels { more: Cow::Borrowed("more\n"), ..Default::default() };
+	assert_eq!(config_error(Config { labels: labels.clone(), ..Default::default() }), ConfigError::ControlCharacter { field: "labels.more" });
+	let labels = Labels { eta: Cow::Borrowed("\rETA"), ..Default::default() };
+	assert_eq!(config_error(Config { labels, ..Default::default() }), ConfigError::ControlCharacter { field: "labels.eta" });
+	// Escape sequences aren't
+	assert!(Config { prefix: "\x1b[1mbold\x1b[0m", ..Default::default() }.validate().is_ok());
+}
+
+#[test]
+#[should_panic(expected = "phase 3 is out of range, there are only 2 phases")]
+fn phase_out_of_range() {
+	Phases::new(&["fetch", "build"], Config::default()).start(2, 1);
+}
+
+#[test]
+#[should_panic(expected = "`labels.more` contains a control character")]
+fn multi_bar_labels_with_control_characters() {
+	MultiBar::with_writer(1, Arc::new(Mutex::new(Vec::new()))).set_labels(Labels { more: Cow::Borrowed("more\n"), ..Default::default() });
+}
+
+#[test]
+fn config_error_requires() {
+	let error = config_error(Config { count_in_bar: true, show_bar: false, ..Default::default() });
+	assert_eq!(error, ConfigError::Requires { option: "count_in_bar", requires: "show_bar" });
+}
+
+#[test]
+fn number_grouping() {
+	for (number, expected) in [
+		(0, "0"), (123, "123"), (1234, "1,234"), (123_456, "123,456"), (1_234_567, "1,234,567"),
+		(u64::MAX, "18,446,744,073,709,551,615"),
+	] {
+		assert_eq!(format_number(number, ','), expected);
+		// The built-in grouping, whatever the features
+		assert_eq!(group_digits(number, '\''), expected.replace(',', "'"));
+	}
+}
+
+#[test]
+fn group_separator() {
+	let (bar, _, capture) = captured(1_000_000, Config { group_separator: '.', ..Default::default() });
+	drop(bar);
+	let separator = if cfg!(feature = "num-format") { ',' } else { '.' };
+	assert!(capture.last_frame().unwrap().contains(&format!(" 0 / 1{separator}000{separator}000 ")));
+}
+
+fn time(secs: u64, format: TimeFormat) -> String {
+	Time { millis: secs * 1000, format, precise: false }.to_string()
+}
+
+#[test]
+fn human_time_units() {
+	for (secs, expected) in [
+		(0, "     0s"), (59, "    59s"), (60, " 1m 00s"), (3599, "59m 59s"), (3600, " 1h 00m"), (86399, "23h 59m"),
+		(86400, "  1d 0h"), (100 * 86400 - 1, "99d 23h"), (100 * 86400, "    ??d"),
+	] {
+		assert_eq!(time(secs, TimeFormat::Human), expected, "{secs}s");
+	}
+}
+
+#[test]
+fn clock_time_units() {
+	for (secs, expected) in [(59, "00:00:59"), (60, "00:01:00"), (3599, "00:59:59"), (3600, "01:00:00"), (100 * 3600 - 1, "99:59:59")] {
+		assert_eq!(time(secs, TimeFormat::Clock), expected, "{secs}s");
+	}
+}
+
+#[test]
+fn precise_seconds() {
+	let precise = |millis| Time { millis, format: TimeFormat::Clock, precise: true };
+
+	for (millis, expected) in [(0, "    0.0s"), (9900, "    9.9s"), (9999, "    9.9s"), (10_000, "00:00:10")] {
+		assert_eq!(precise(millis).to_string(), expected, "{millis} ms");
+	}
+
+	// The ETA is rounded up to the tenths it shows, and past 9.9s to whole seconds
+	for (millis, expected) in [(0, "    0.0s"), (9801, "    9.9s"), (9901, "00:00:10"), (10_001, "00:00:11")] {
+		assert_eq!(precise(millis).ceil().to_string(), expected, "{millis} ms");
+	}
+}
+
+#[test]
+fn countdown() {
+	let (bar, clock, capture) = captured(100, Config { countdown: true, ..Default::default() });
+	clock.advance(Duration::from_secs(10));
+	bar.inc(50);
+	clock.advance(Duration::from_secs(10));
+	bar.inc(50);
+	drop(bar);
+
+	// What is left, from all of it down to nothing
+	assert_eq!(capture.frames(), [
+		" 00:00:00 100 / 100 [####################] 100% ETA --:--:--",
+		" 00:00:10  50 / 100 [###########         ]  50% ETA 00:00:10",
+		" 00:00:20   0 / 100 [                    ]   0% ETA 00:00:00",
+		" 00:00:20   0 / 100 [                    ]   0% ETA 00:00:00",
+	]);
+}
+
+#[test]
+fn huge_len_fills_exactly() {
+	let config = Config { rounding: Rounding::Floor, show_count: false, throttle: Duration::ZERO, ..Default::default() };
+	let (bar, _, capture) = captured(u64::MAX, config);
+	bar.inc(u64::MAX / 2);
+	bar.inc(u64::MAX / 2);
+	bar.inc(1);
+
+	// One step short of the end is a cell short of a full bar, which f64 can't tell apart
+	assert_eq!(capture.frames(), [
+		" 00:00:00 [#                             ]   0% ETA --:--:--",
+		" 00:00:00 [###############               ]  50% ETA 00:00:00",
+		" 00:00:00 [############################# ] 100% ETA 00:00:00",
+		" 00:00:00 [##############################] 100% ETA 00:00:00",
+	]);
+}
+
+#[test]
+fn wide_characters_fill_the_width() {
+	let (bar, _, capture) = captured(100, Config { prefix: "处理中", throttle: Duration::ZERO, ..Default::default() });
+	bar.inc(50);
+	bar.set_message("🚀 启动");
+	drop(bar);
+
+	let (wide, _, wide_capture) = captured(100, Config { style: Style::Edged('🟩', '🟨'), space_char: '⬜', width: Some(61), ..Default::default() });
+	drop(wide);
+
+	// The CJK prefix and message take two cells a character
+	assert_eq!(capture.frames(), [
+		"处理中 00:00:00   0 / 100 [#             ]   0% ETA --:--:--",
+		"处理中 00:00:00  50 / 100 [########      ]  50% ETA 00:00:00",
+		"处理中 🚀 启动 00:00:00  50 / 100 [####  ]  50% ETA 00:00:00",
+	]);
+	// And so do the glyphs, with the odd cell left over padded after the bar
+	assert_eq!(wide_capture.last_frame().unwrap(), " 00:00:00   0 / 100 [🟨⬜⬜⬜⬜⬜⬜⬜⬜⬜]    0% ETA --:--:--");
+	assert!(capture.frames().iter().all(|frame| text_width(frame) == 60));
+	assert_eq!(text_width(&wide_capture.last_frame().unwrap()), 61);
+}
+
+#[test]
+fn finish_icons() {
+	let mut frames = Vec::new();
+
+	for config in [Config::ascii(), Config::unicode(), Config::default(), Config { finish_icon: Some(('v', 'x')), ..Config::minimal() }] {
+		let (bar, _, succeeded) = captured(10, config.clone());
+		bar.inc(10);
+		drop(bar);
+		let (bar, _, failed) = captured(10, config);
+		bar.inc(5);
+		bar.abandon();
+		frames.push([succeeded.last_frame().unwrap(), failed.last_frame().unwrap()]);
+	}
+
+	// The icon takes the place of the edge, or comes before the percentage without a bar
+	assert_eq!(frames, [
+		[" 00:00:00 10 / 10 [#####################v] 100% ETA 00:00:00", " 00:00:00  5 / 10 [#####x    ] !!  50% failed after 00:00:00"],
+		[" 00:00:00 10 / 10 [█████████████████████✓] 100% ETA 00:00:00", " 00:00:00  5 / 10 [█████✗    ] !!  50% failed after 00:00:00"],
+		[" 00:00:00 10 / 10 [######################] 100% ETA 00:00:00", " 00:00:00  5 / 10 [######    ] !!  50% failed after 00:00:00"],
+		[" v 100%", " x !!  50%"],
+	]);
+}
+
+#[test]
+fn minimal() {
+	let (bar, _, capture) = captured(100, Config { throttle: Duration::ZERO, ..Config::minimal() });
+	bar.inc(42);
+	assert_eq!(capture.last_frame().unwrap(), "  42%");
+	let (bar, _, capture) = captured(100, Config { prefix: "copying", throttle: Duration::ZERO, ..Config::minimal() });
+	bar.inc(42);
+	assert_eq!(capture.last_frame().unwrap(), "copying  42%");
+}
+
+#[test]
+fn initial_frame() {
+	const INITIAL: &str = " 00:00:00  0 / 10 [#                     ]   0% ETA --:--:--";
+	let capture = FrameCapture::new();
+	let bar = Bar::with_clock(10, Config { width: Some(60), writer: Some(capture.writer()), ..Default::default() }, ManualClock::new());
+	assert_eq!(capture.frames(), [INITIAL]);
+	bar.inc(1);
+	drop(bar);
+	assert_eq!(capture.frames()[0], INITIAL);
+	assert_eq!(capture.frames().len(), 2);
+}
+
+#[test]
+fn columns_fallback() {
+	// Not a terminal, so it is down to `COLUMNS`
+	let file = File::open(std::env::current_exe().unwrap()).unwrap();
+	let width = |columns: Option<&str>| line_width(None, Some(&file), |name| columns.filter(|_| name == "COLUMNS").map(str::to_owned));
+
+	assert_eq!(width(Some("120")), Some(120));
+	assert_eq!(width(Some(" 100\n")), Some(100));
+	assert_eq!(width(Some("0")), None);
+	assert_eq!(width(Some("-5")), None);
+	assert_eq!(width(Some("wide")), None);
+	assert_eq!(width(Some("")), None);
+	assert_eq!(width(None), None);
+	assert_eq!(line_width(Some(40), Some(&file), |_| Some("120".to_owned())), Some(40));
+}
+
+#[test]
+fn progress_macro() {
+	let (capture, mut seen) = (FrameCapture::new(), Vec::new());
+
+	crate::progress!(config = sunk(&capture), for i in &[1, 2, 3, 4] {
+		if i % 2 == 0 {
+			continue;
+		}
+
+		seen.push(*i);
+	});
+
+	assert_eq!(seen, [1, 3]);
+	assert!(capture.last_frame().unwrap().contains(" 4 / 4 "));
+
+	// Not an `ExactSizeIterator`, and cut short by a `break` out of the outer loop
+	crate::progress!(total = 5, config = sunk(&capture), 'outer: for i in (0..).filter(|i| i % 3 == 0) {
+		for j in 0..i {
+			if j == 6 {
+				break 'outer;
+			}
+		}
+
+		seen.push(i);
+	});
+
+	assert_eq!(seen, [1, 3, 0, 3, 6]);
+	assert!(failed(capture.last_frame()));
+
+	let find = |capture: &FrameCapture| {
+		crate::progress!(config = sunk(capture), for i in 0..10 {
+			if i == 2 {
+				return Some(i);
+			}
+		});
+
+		None
+	};
+
+	assert_eq!(find(&capture), Some(2));
+	assert!(capture.last_frame().unwrap().contains("  3 / 10 "));
+}
+
+#[test]
+fn suspending_a_sunk_bar_leaves_the_terminal_alone() {
+	let (capture, terminal) = (FrameCapture::new(), Arc::new(Mutex::new(Vec::new())));
+	// Where the clearing would go if it weren't skipped
+	let bar = Bar::new(10, Config { writer: Some(terminal.clone()), ..sunk(&capture) });
+	bar.suspend(|| ());
+	bar.inc(1);
+	drop(bar);
+
+	assert!(terminal.lock().unwrap().is_empty());
+	assert_eq!(capture.frames().len(), 4);
+}
+
+// The positions and lengths passed to `on_progress`
+type ProgressCalls = Arc<Mutex<Vec<(u64, u64)>>>;
+
+fn progress_calls() -> (ProgressCallback, ProgressCalls) {
+	let calls = Arc::new(Mutex::new(Vec::new()));
+	let callback = calls.clone();
+	(Arc::new(move |pos, len| callback.lock().unwrap().push((pos, len))), calls)
+}
+
+#[test]
+fn saturating_steps_report_progress() {
+	let (capture, (on_progress, calls)) = (FrameCapture::new(), progress_calls());
+	let bar = Bar::new(10, Config { on_progress: Some(on_progress), ..sunk(&capture) });
+	bar.inc_saturating(5);
+	bar.inc_saturating(10);
+	assert_eq!(*calls.lock().unwrap(), [(5, 10), (10, 10)]);
+}
+
+#[test]
+fn observed_counters_report_progress() {
+	let (counter, capture, (on_progress, calls)) = (ProgressCounter::new(), FrameCapture::new(), progress_calls());
+	let _bar = Bar::observe(counter.clone(), 10, Config { on_progress: Some(on_progress), ..sunk(&capture) });
+	counter.add(10);
+	let deadline = Instant::now() + Duration::from_secs(10);
+
+	while calls.lock().unwrap().is_empty() {
+		assert!(Instant::now() < deadline, "the tick never reported the progress");
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	assert_eq!(*calls.lock().unwrap(), [(10, 10)]);
+}
+
+#[test]
+fn len_shrinking_below_pos() {
+	const FULL: &str = " 00:00:00 50 / 50 [######################] 100% ETA 00:00:00";
+	let (bar, _, capture) = captured(80, Config { throttle: Duration::ZERO, ..Default::default() });
+	bar.inc(60);
+	bar.set_len(50);
+	assert_eq!(capture.last_frame().unwrap(), FULL);
+	drop(bar);
+	assert_eq!(capture.last_frame().unwrap(), FULL);
+}
+
+#[test]
+fn virtual_terminal_enablement() {
+	// A pipe or a file, whose mode can't be read, is drawn to as usual
+	assert!(enable_virtual_terminal(|| None, |_| unreachable!()));
+
+	let set = Mutex::new(None);
+	assert!(enable_virtual_terminal(|| Some(0x0003), |mode| set.lock().unwrap().replace(mode).is_none()));
+	assert_eq!(*set.lock().unwrap(), Some(0x0007));
+
+	// A legacy console falls back to a line per frame
+	assert!(!enable_virtual_terminal(|| Some(0x0003), |_| false));
+}
+
+#[test]
+fn reading_bytes_leaves_the_rates_alone() {
+	let run = |read: bool| {
+		let config = Config { show_current_rate: true, eta_samples: 2, throttle: Duration::ZERO, ..Default::default() };
+		let (bar, clock, capture) = captured(100, config);
+
+		for delta in [50, 1, 20] {
+			clock.advance(Duration::from_secs(1));
+
+			// Halfway between the frames, where a sample would change the current rate
+			if read {
+				assert_eq!(bar.bytes() % 1024, 0);
+			}
+
+			clock.advance(Duration::from_secs(1));
+			bar.inc(delta);
+			bar.inc_bytes(delta * 1024);
+		}
+
+		(bar.bytes(), capture.frames())
+	};
+
+	assert_eq!(run(true), run(false));
+	assert_eq!(run(true).0, 71 * 1024);
+}
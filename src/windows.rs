@@ -0,0 +1,79 @@
+const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+/// Turns escape sequences on through the mode from `get_mode`, which fails on anything other than a console (a pipe or
+/// a file), which is left alone. Returns `false` for a console that can't be switched, which has to be drawn to line by
+/// line instead.
+pub(crate) fn enable_virtual_terminal(get_mode: impl FnOnce() -> Option<u32>, set_mode: impl FnOnce(u32) -> bool) -> bool {
+	get_mode().is_none_or(|mode| set_mode(mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING))
+}
+
+#[cfg(all(windows, feature = "windows-console"))]
+pub(crate) use console::{terminal_width, virtual_terminal};
+
+// The console calls only exist on Windows, unlike what is made of their results above
+#[cfg(all(windows, feature = "windows-console"))]
+mod console {
+	use std::{ffi::c_void, io::stderr, os::windows::io::AsRawHandle, sync::OnceLock};
+
+	use super::enable_virtual_terminal;
+
+	#[repr(C)]
+	struct Coord {
+		x: i16,
+		y: i16,
+	}
+
+	#[repr(C)]
+	struct SmallRect {
+		left: i16,
+		top: i16,
+		right: i16,
+		bottom: i16,
+	}
+
+	#[repr(C)]
+	struct ConsoleScreenBufferInfo {
+		size: Coord,
+		cursor_position: Coord,
+		attributes: u16,
+		window: SmallRect,
+		maximum_window_size: Coord,
+	}
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+		fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+		fn GetConsoleScreenBufferInfo(console: *mut c_void, info: *mut ConsoleScreenBufferInfo) -> i32;
+	}
+
+	/// Makes the console interpret escape sequences on Windows 10+, once (see `enable_virtual_terminal`).
+	pub(crate) fn virtual_terminal() -> bool {
+		static ENABLED: OnceLock<bool> = OnceLock::new();
+
+		*ENABLED.get_or_init(|| {
+			let console = stderr().as_raw_handle();
+
+			// SAFETY: the handle stays valid for the lifetime of the process, and the calls fail harmlessly on handles that
+			// aren't consoles
+			let get_mode = || {
+				let mut mode = 0;
+				(unsafe { GetConsoleMode(console, &mut mode) } != 0).then_some(mode)
+			};
+			let set_mode = |mode| unsafe { SetConsoleMode(console, mode) } != 0;
+			enable_virtual_terminal(get_mode, set_mode)
+		})
+	}
+
+	pub(crate) fn terminal_width() -> Option<u64> {
+		// SAFETY: all-zero is a valid value for the plain integer fields
+		let mut info: ConsoleScreenBufferInfo = unsafe { std::mem::zeroed() };
+
+		// SAFETY: see `virtual_terminal`
+		if unsafe { GetConsoleScreenBufferInfo(stderr().as_raw_handle(), &mut info) } == 0 {
+			return None;
+		}
+
+		u64::try_from(info.window.right - info.window.left + 1).ok()
+	}
+}